@@ -3,16 +3,18 @@ use intcode::intcode::*;
 
 fn main() {
     let program_spec = first_line(file_to_vec("input.txt".to_string()).unwrap());
-    let part_1_result = run_emulator_verbs(&program_spec, 12, 2, false);
-    println!("PART 1 OUTPUT: {}", part_1_result);
+    match run_emulator_verbs(&program_spec, 12, 2, false) {
+        Ok(part_1_result) => println!("PART 1 OUTPUT: {}", part_1_result),
+        Err(error) => println!("PART 1 FAILED: {}", error),
+    }
     let (noun, verb) = find_part_2(&program_spec, false);
     println!("PART 2 OUTPUT: {}", 100 * noun + verb);
 }
 
-fn find_part_2(program_spec: &String, _debug: bool) -> (i32, i32) {
+fn find_part_2(program_spec: &String, _debug: bool) -> (i64, i64) {
     for noun in 1..99 {
         for verb in 1..99 {
-            if 19690720 == run_emulator_verbs(program_spec, noun, verb, _debug) {
+            if Ok(19690720) == run_emulator_verbs(program_spec, noun, verb, _debug) {
                 return (noun, verb);
             }
         }
@@ -20,11 +22,16 @@ fn find_part_2(program_spec: &String, _debug: bool) -> (i32, i32) {
     (-1, -1)
 }
 
-fn run_emulator_verbs(program_spec: &String, noun: i32, verb: i32, _debug: bool) -> i32 {
-    let mut emulator = Emulator::new(comma_separated_ints_to_vec(program_spec), vec![], _debug);
+fn run_emulator_verbs(
+    program_spec: &String,
+    noun: i64,
+    verb: i64,
+    _debug: bool,
+) -> Result<i64, IntcodeError> {
+    let mut emulator = Emulator::new(comma_separated_i64_to_vec(program_spec), vec![], _debug);
     emulator.program[1] = noun;
     emulator.program[2] = verb;
-    emulator.run_program();
+    emulator.run_program()?;
     let result = emulator.program[0];
-    return result;
+    Ok(result)
 }
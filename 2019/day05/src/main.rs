@@ -1,25 +1,33 @@
 use common::*;
 use intcode::intcode::*;
+use std::error::Error;
 
 fn main() {
     let program_spec = first_line(file_to_vec("input.txt".to_string()).unwrap());
-    println!(
-        "PART 1 OUTPUT: {}",
-        run_emulator(program_spec.to_string(), "1".to_string(), false)
-    );
-    println!(
-        "PART 2 OUTPUT: {}",
-        run_emulator(program_spec.to_string(), "5".to_string(), false)
-    );
+    match run_emulator(program_spec.to_string(), "1".to_string(), false) {
+        Ok(output) => println!("PART 1 OUTPUT: {}", output),
+        Err(error) => println!("PART 1 FAILED: {}", error),
+    }
+    match run_emulator(program_spec.to_string(), "5".to_string(), false) {
+        Ok(output) => println!("PART 2 OUTPUT: {}", output),
+        Err(error) => println!("PART 2 FAILED: {}", error),
+    }
 }
 
-fn run_emulator(program_spec: String, input_spec: String, debug: bool) -> i32 {
+fn run_emulator(
+    program_spec: String,
+    input_spec: String,
+    debug: bool,
+) -> Result<i64, Box<dyn Error>> {
     let mut emulator = prepare_emulator(program_spec, input_spec, debug);
     loop {
-        match emulator.run_program() {
+        match emulator.run_program()? {
             RunSignal::Halt => break,
             _ => continue,
         }
     }
-    return emulator.outputs.pop().unwrap();
+    emulator
+        .outputs
+        .pop()
+        .ok_or_else(|| "the program halted without any output".into())
 }
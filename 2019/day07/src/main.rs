@@ -1,14 +1,20 @@
 use common::*;
-use intcode::intcode::*;
+use intcode::dataflow::*;
 use itertools::Itertools;
 
 fn main() {
     let program_spec = first_line(file_to_vec("input.txt".to_string()).unwrap());
-    let mut max_signal = std::i32::MIN;
-    let mut max_combo = vec![0,0,0,0,0];
-    
+    let mut max_signal = i64::MIN;
+    let mut max_combo = vec![0, 0, 0, 0, 0];
+
     for combo in (0..=4).permutations(5) {
-        let signal = run_amp_sequence(&program_spec, &combo, false);
+        let signal = match run_amp_sequence(&program_spec, &combo, false) {
+            Ok(signal) => signal,
+            Err(error) => {
+                println!("PART 1 FAILED: {:?} -> {}", combo, error);
+                continue;
+            }
+        };
         if signal > max_signal {
             max_signal = signal;
             max_combo = combo;
@@ -17,9 +23,15 @@ fn main() {
     }
     println!("PART 1 MAX: {:?} -> {}", max_combo, &max_signal);
 
-    max_signal = std::i32::MIN;
+    max_signal = i64::MIN;
     for combo in (5..=9).permutations(5) {
-        let signal = run_amp_feedback_sequence(&program_spec, &combo, false);
+        let signal = match run_amp_feedback_sequence(&program_spec, &combo, false) {
+            Ok(signal) => signal,
+            Err(error) => {
+                println!("PART 2 FAILED: {:?} -> {}", combo, error);
+                continue;
+            }
+        };
         if signal > max_signal {
             max_signal = signal;
             max_combo = combo;
@@ -29,127 +41,152 @@ fn main() {
     println!("PART 2 MAX: {:?} -> {}", max_combo, &max_signal);
 }
 
-fn run_amp_feedback_sequence(program_spec: &String, phases: &Vec<i32>, debug: bool) -> i32 {
-    //setup
-    let mut emulators: Vec<Emulator> = vec![];
-    let mut first = true;
-    for phase in phases {
-        let mut emulator = Emulator::new(
-            common::comma_separated_ints_to_vec(program_spec),
-            vec![*phase],
-            debug,
-        );
-        if first {
-            emulator.inputs.push(0); // seeding signal
-            first = false;
-        }
-        emulators.push(emulator);
-    }
-    
-    let mut current_emulator = 0;
-    loop {
-        match emulators[current_emulator].run_program() {
-            RunSignal::Halt => {
-                if current_emulator == emulators.len() - 1 {
-                    return emulators[current_emulator].outputs.pop().unwrap();
-                }
-            },
-            RunSignal::Output(value) => {
-                let next_emulator = ( current_emulator + 1) % 5;
-                emulators[next_emulator].inputs.push(value);
-            }
-            //RunSignal::NoInput =>
-            _ => continue // if input is requested, then we need to move on to the next emulator. eventually we'll loop around and output should feed to input, and it'll resolve
-        }
-        current_emulator = ( current_emulator + 1) % emulators.len();
-    }
+fn run_amp_feedback_sequence(
+    program_spec: &str,
+    phases: &[i64],
+    debug: bool,
+) -> Result<i64, DataflowError> {
+    let program = common::comma_separated_i64_to_vec(&program_spec.to_string());
+    let sink = phases.len() - 1;
+    let run = Dataflow::feedback_loop(program, phases, 0, debug).run(sink)?;
+    run.signal().ok_or(DataflowError::NoSignal { sink })
 }
 
-fn run_amp_sequence(program_spec: &String, phases: &Vec<i32>, debug: bool) -> i32 {
+fn run_amp_sequence(program_spec: &str, phases: &[i64], debug: bool) -> Result<i64, DataflowError> {
     //println!("### RUN AMP SEQUENCE {:?}", phases);
-    let mut signal = 0;
-
-    for phase in phases {
-        signal = run_amp(&program_spec, *phase, signal, debug);
-    }
-
-    //println!("### RUN AMP SEQUENCE {:?} -> {}", &phases, signal);
-    return signal;
-}
-
-fn run_amp(program_spec: &String, phase: i32, signal: i32, debug: bool) -> i32 {
-    //println!("### RUN AMP p:{}, s:{}", phase, signal);
-
-    let mut emulator = Emulator::new(
-        common::comma_separated_ints_to_vec(program_spec),
-        vec![],
-        debug,
-    );
-    
-    emulator.inputs.append(&mut vec![phase, signal]);
-
-    loop {
-        match emulator.run_program() {
-            RunSignal::Halt => break,
-            _ => continue
-        }
-    }
-    
-    let result = emulator.outputs.pop().unwrap();
+    let program = common::comma_separated_i64_to_vec(&program_spec.to_string());
+    let sink = phases.len() - 1;
+    let run = Dataflow::chain(program, phases, 0, debug).run(sink)?;
 
-    return result;
+    //println!("### RUN AMP SEQUENCE {:?} -> {:?}", &phases, run.outputs);
+    run.signal().ok_or(DataflowError::NoSignal { sink })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use intcode::assembler::*;
+
+    //(0 * 10) + 4 => 4
+    //(4 * 10) + 3 => 43
+    //(43 * 10) + 2 => 432
+    //(432 * 10) + 1 => 4321
+    //(4321 * 10) + 0 => 43210
+    const SHIFT_AND_ADD: &str = "
+                in -> [phase]
+                in -> [signal]
+                mul [signal], #10 -> [signal]
+                add [signal], [phase] -> [phase]
+                out [phase]
+                hlt
+        phase:  db 0
+        signal: db 0
+    ";
 
     #[test]
     fn run_amp_runs() {
-        let signal = run_amp(
-            &"3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0".to_string(),
-            4,
-            0,
-            true,
-        );
+        let signal =
+            run_amp_sequence(&assemble_to_string(SHIFT_AND_ADD).unwrap(), &[4], true).unwrap();
         println!("SIGNAL: {}", signal);
         assert_eq!(4, signal);
     }
 
     #[test]
     fn run_amp_sequence_works_1() {
-        //IN([15])
-        //IN([16])
-        //MUL([16], 10, [16])
-        //ADD([16], [15], [15])
-        //OUT([15])
-        //HALT
-        //(0 * 10) + 4 => 4
-        //(4 * 10) + 3 => 43
-        //(43 * 10) + 2 => 432
-        //(432 * 10) + 1 => 4321
-        //(4321 * 10) + 0 => 43210
         let signal = run_amp_sequence(
-            &"3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0".to_string(),
-            vec![4, 3, 2, 1, 0],
+            &assemble_to_string(SHIFT_AND_ADD).unwrap(),
+            &[4, 3, 2, 1, 0],
             true,
-        );
+        )
+        .unwrap();
         assert_eq!(43210, signal);
     }
 
     #[test]
     fn run_amp_sequence_works_2() {
+        let program = "
+                    in -> [phase]
+                    in -> [signal]
+                    mul [signal], #10 -> [signal]
+                    mul [phase], #-1 -> [phase]
+                    add #5, [phase] -> [phase]
+                    add [signal], [phase] -> [phase]
+                    out [phase]
+                    hlt
+            phase:  db 0
+            signal: db 0
+        ";
         let signal = run_amp_sequence(
-            &"3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0".to_string(),
-            vec![0, 1, 2, 3, 4],
+            &assemble_to_string(program).unwrap(),
+            &[0, 1, 2, 3, 4],
             false,
-        );
+        )
+        .unwrap();
         assert_eq!(54321, signal);
     }
 
     #[test]
     fn run_amp_sequence_works_3() {
-        let signal = run_amp_sequence(&"3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0".to_string(), vec![1,0,4,3,2], false);
+        let program = "
+                    in -> [phase]
+                    in -> [signal]
+                    mul [signal], #10 -> [signal]
+                    add [phase], #-2 -> [phase]
+                    lt [phase], #0 -> [wrap]
+                    mul [wrap], #7 -> [wrap]
+                    add [wrap], [phase] -> [phase]
+                    add [signal], [phase] -> [phase]
+                    out [phase]
+                    hlt
+            phase:  db 0
+            signal: db 0
+            wrap:   db 0
+        ";
+        let signal = run_amp_sequence(
+            &assemble_to_string(program).unwrap(),
+            &[1, 0, 4, 3, 2],
+            false,
+        )
+        .unwrap();
         assert_eq!(65210, signal);
     }
+
+    #[test]
+    fn run_amp_feedback_sequence_works() {
+        // adds its phase to every signal that comes round, halting after five
+        let program = "
+                    in -> [phase]
+            loop:   in -> [signal]
+                    add [signal], [phase] -> [signal]
+                    out [signal]
+                    add [count], #1 -> [count]
+                    eq [count], #5 -> [done]
+                    jf [done], #loop
+                    hlt
+            phase:  db 0
+            signal: db 0
+            count:  db 0
+            done:   db 0
+        ";
+        let signal = run_amp_feedback_sequence(
+            &assemble_to_string(program).unwrap(),
+            &[9, 8, 7, 6, 5],
+            false,
+        )
+        .unwrap();
+        assert_eq!(5 * 35, signal);
+    }
+
+    #[test]
+    fn amps_that_halt_without_output_fail() {
+        let program = assemble_to_string("hlt").unwrap();
+        assert_eq!(
+            Err(DataflowError::NoSignal { sink: 4 }),
+            run_amp_feedback_sequence(&program, &[5, 6, 7, 8, 9], false)
+        );
+        assert_eq!(
+            Err(DataflowError::NoSignal { sink: 4 }),
+            run_amp_sequence(&program, &[0, 1, 2, 3, 4], false)
+        );
+    }
 }
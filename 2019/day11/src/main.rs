@@ -1,18 +1,25 @@
 use common::*;
 use intcode::intcode::*;
+use intcode::io;
+use std::cell::RefCell;
 use std::collections::HashMap;
 
 fn main() {
     let program_spec = first_line(file_to_vec("input.txt".to_string()).unwrap());
-    let canvas = run_emulator(program_spec.clone(), Colour::Black, false);
-    let unique = canvas.keys().count();
-    //println!("{:?}", &canvas);
-    print_canvas(&canvas);
-    println!("Painted {} squares at least once", unique);
-
-    let canvas = run_emulator(program_spec.clone(), Colour::White, false);
-    //println!("{:?}", &canvas);
-    print_canvas(&canvas);
+    match run_emulator(program_spec.clone(), Colour::Black, false) {
+        Ok(canvas) => {
+            let unique = canvas.keys().count();
+            //println!("{:?}", &canvas);
+            print_canvas(&canvas);
+            println!("Painted {} squares at least once", unique);
+        }
+        Err(error) => println!("PART 1 FAILED: {}", error),
+    }
+
+    match run_emulator(program_spec.clone(), Colour::White, false) {
+        Ok(canvas) => print_canvas(&canvas),
+        Err(error) => println!("PART 2 FAILED: {}", error),
+    }
 }
 
 #[derive(Debug)]
@@ -21,7 +28,7 @@ enum Colour {
     White,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum Direction {
     Up,
     Down,
@@ -41,67 +48,69 @@ enum NextAction {
     Move,
 }
 
+struct Robot {
+    canvas: HashMap<(i32, i32), Colour>,
+    position: (i32, i32),
+    facing: Direction,
+    next: NextAction,
+}
+
 fn run_emulator(
     program_spec: String,
     starting_colour: Colour,
     debug: bool,
-) -> HashMap<(i32, i32), Colour> {
+) -> Result<HashMap<(i32, i32), Colour>, IntcodeError> {
     let mut canvas: HashMap<(i32, i32), Colour> = HashMap::new();
-    let mut position = (0, 0);
-    canvas.insert(position, starting_colour);
-    let mut facing = Direction::Up;
+    canvas.insert((0, 0), starting_colour);
+    let robot = RefCell::new(Robot {
+        canvas,
+        position: (0, 0),
+        facing: Direction::Up,
+        next: NextAction::Paint,
+    });
     let mut emulator = prepare_emulator(program_spec, "".to_string(), debug);
-    let mut next = NextAction::Paint;
-
-    loop {
-        match emulator.run_program() {
-            RunSignal::Halt => break,
-            RunSignal::NoInput => {
-                //println!("Read");
-                match canvas.get(&position) {
-                    Some(c) => match c {
-                        Colour::White => emulator.inputs.push(1),
-                        Colour::Black => emulator.inputs.push(0),
-                    },
-                    None => emulator.inputs.push(0),
-                }
-            } //give it what the robot sees
-            RunSignal::Output(value) => match next {
-                NextAction::Paint => {
-                    match value {
-                        1 => {
-                            //println!("Paint {:?} {:?}", position, Colour::White);
-                            canvas.insert(position, Colour::White);
-                        }
-                        _ => {
-                            //println!("Paint {:?} {:?}", position, Colour::Black);
-                            canvas.insert(position, Colour::Black);
-                        }
-                    };
-                    next = NextAction::Move;
-                }
-                NextAction::Move => {
-                    match value {
-                        0 => {
-                            let (p, f) = calculate_robot_state(position, facing, Turn::Left);
-                            position = p;
-                            facing = f;
-                        }
-                        _ => {
-                            let (p, f) = calculate_robot_state(position, facing, Turn::Right);
-                            position = p;
-                            facing = f;
-                        }
-                    }
-                    //println!("Moved to {:?}, facing {:?}", position, facing);
-                    next = NextAction::Paint;
-                }
-            }, //paint!
+
+    //give it what the robot sees
+    let mut camera = io::from_fn(|| {
+        let robot = robot.borrow();
+        match robot.canvas.get(&robot.position) {
+            Some(Colour::White) => Some(1),
+            _ => Some(0),
         }
-    }
-    //emulator.outputs.pop().unwrap()
+    });
+    //paint or move!
+    let mut controls = io::to_fn(|value| {
+        let mut robot = robot.borrow_mut();
+        match robot.next {
+            NextAction::Paint => {
+                let colour = match value {
+                    1 => Colour::White,
+                    _ => Colour::Black,
+                };
+                //println!("Paint {:?} {:?}", robot.position, colour);
+                let position = robot.position;
+                robot.canvas.insert(position, colour);
+                robot.next = NextAction::Move;
+            }
+            NextAction::Move => {
+                let turn = match value {
+                    0 => Turn::Left,
+                    _ => Turn::Right,
+                };
+                let (p, f) = calculate_robot_state(robot.position, robot.facing, turn);
+                robot.position = p;
+                robot.facing = f;
+                //println!("Moved to {:?}, facing {:?}", robot.position, robot.facing);
+                robot.next = NextAction::Paint;
+            }
+        }
+    });
+
+    // the camera never runs dry and we never set any watchpoints or breakpoints,
+    // so this only comes back once the robot halts
+    emulator.run_with(&mut camera, &mut controls)?;
 
-    canvas
+    Ok(robot.into_inner().canvas)
 }
 
 fn print_canvas(canvas: &HashMap<(i32, i32), Colour>) {
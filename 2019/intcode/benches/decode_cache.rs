@@ -0,0 +1,100 @@
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+use intcode::intcode::*;
+
+// the real puzzle inputs, which spend nearly all their time in tight loops
+const AMPLIFIERS: &str = include_str!("../../day07/input.txt");
+const BOOST: &str = include_str!("../../day09/input.txt");
+
+fn emulator(program: &[i64], inputs: Vec<i64>, cached: bool) -> Emulator {
+    let mut emulator = Emulator::new(program.to_vec(), inputs, false);
+    emulator.set_decode_cache(cached);
+    emulator
+}
+
+fn permutations(values: Vec<i64>) -> Vec<Vec<i64>> {
+    if values.len() <= 1 {
+        return vec![values];
+    }
+    let mut all = vec![];
+    for (i, first) in values.iter().enumerate() {
+        let mut rest = values.clone();
+        rest.remove(i);
+        for mut permutation in permutations(rest) {
+            permutation.insert(0, *first);
+            all.push(permutation);
+        }
+    }
+    all
+}
+
+// day 7 part 1, every ordering of phases through a chain of amplifiers
+fn amplifier_chains(program: &[i64], cached: bool) -> i64 {
+    let mut best = i64::MIN;
+    for phases in permutations((0..=4).collect()) {
+        let mut signal = 0;
+        for phase in phases {
+            let mut amplifier = emulator(program, vec![phase, signal], cached);
+            while let RunSignal::Output(output) = amplifier.run_program().unwrap() {
+                signal = output;
+            }
+        }
+        best = best.max(signal);
+    }
+    best
+}
+
+// day 7 part 2, the same with the last amplifier fed back into the first
+fn amplifier_loops(program: &[i64], cached: bool) -> i64 {
+    let mut best = i64::MIN;
+    for phases in permutations((5..=9).collect()) {
+        let mut amplifiers: Vec<Emulator> = phases
+            .iter()
+            .map(|phase| emulator(program, vec![*phase], cached))
+            .collect();
+        let mut signal = 0;
+        while !amplifiers[4].is_halted() {
+            for amplifier in &mut amplifiers {
+                amplifier.inputs.push(signal);
+                if let RunSignal::Output(output) = amplifier.run_program().unwrap() {
+                    signal = output;
+                }
+            }
+        }
+        best = best.max(signal);
+    }
+    best
+}
+
+// day 9 part 2, which runs for a few hundred thousand instructions
+fn boost(program: &[i64], cached: bool) -> i64 {
+    let mut emulator = emulator(program, vec![2], cached);
+    let mut coordinates = 0;
+    while let RunSignal::Output(output) = emulator.run_program().unwrap() {
+        coordinates = output;
+    }
+    coordinates
+}
+
+// runs a program, with or without the decode cache
+type Run = fn(&[i64], bool) -> i64;
+
+fn benchmarks(c: &mut Criterion) {
+    let amplifiers = common::comma_separated_i64_to_vec(&AMPLIFIERS.trim().to_string());
+    let boost_program = common::comma_separated_i64_to_vec(&BOOST.trim().to_string());
+    let runs: [(&str, &[i64], Run); 3] = [
+        ("day07 chains", &amplifiers, amplifier_chains),
+        ("day07 feedback loops", &amplifiers, amplifier_loops),
+        ("day09 BOOST", &boost_program, boost),
+    ];
+    for (name, program, run) in &runs {
+        let mut group = c.benchmark_group(*name);
+        group.bench_function("decoded every time", |b| b.iter(|| run(program, false)));
+        group.bench_function("decode cache", |b| b.iter(|| run(program, true)));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, benchmarks);
+criterion_main!(benches);
@@ -0,0 +1,206 @@
+use crate::intcode::*;
+use std::fmt;
+
+/*
+    For programs that talk in ASCII: they print text a character per output and
+    read newline-terminated commands a character per input. Anything they output
+    that isn't ASCII is taken to be an answer rather than text.
+*/
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Stop {
+    // the text ended with the prompt we were waiting for
+    Prompt,
+    NoInput,
+    Halt,
+    // a watchpoint, breakpoint or instruction budget the caller set
+    Debug(RunSignal),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Transcript {
+    pub text: String,
+    pub answers: Vec<i64>,
+    pub stop: Stop,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AsciiError {
+    // text to send that has a character the program couldn't read, at this byte offset
+    NotAscii { position: usize, character: char },
+    Emulator(IntcodeError),
+}
+
+impl fmt::Display for AsciiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsciiError::NotAscii {
+                position,
+                character,
+            } => write!(f, "'{}' at position {} isn't ASCII", character, position),
+            AsciiError::Emulator(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for AsciiError {}
+
+impl From<IntcodeError> for AsciiError {
+    fn from(error: IntcodeError) -> AsciiError {
+        AsciiError::Emulator(error)
+    }
+}
+
+pub struct Ascii {
+    pub emulator: Emulator,
+}
+
+pub fn is_ascii(value: i64) -> bool {
+    (0..=127).contains(&value)
+}
+
+impl Ascii {
+    pub fn new(emulator: Emulator) -> Ascii {
+        Ascii { emulator }
+    }
+
+    // nothing is sent if any of it isn't ASCII
+    pub fn send(&mut self, text: &str) -> Result<(), AsciiError> {
+        if let Some((position, character)) = text.char_indices().find(|(_, c)| !c.is_ascii()) {
+            return Err(AsciiError::NotAscii {
+                position,
+                character,
+            });
+        }
+        self.emulator.inputs.extend(text.bytes().map(|b| b as i64));
+        Ok(())
+    }
+
+    // whichever line ending it has, the program gets a plain '\n'
+    pub fn send_line(&mut self, line: &str) -> Result<(), AsciiError> {
+        self.send(line.trim_end_matches(&['\r', '\n'][..]))?;
+        self.emulator.inputs.push(b'\n' as i64);
+        Ok(())
+    }
+
+    // run until the program wants more input or halts
+    pub fn read(&mut self) -> Result<Transcript, IntcodeError> {
+        self.run(None)
+    }
+
+    // like read, but also stopping as soon as the text ends with the prompt
+    pub fn read_until(&mut self, prompt: &str) -> Result<Transcript, IntcodeError> {
+        self.run(Some(prompt))
+    }
+
+    pub fn command(&mut self, line: &str) -> Result<Transcript, AsciiError> {
+        self.send_line(line)?;
+        Ok(self.read()?)
+    }
+
+    fn run(&mut self, prompt: Option<&str>) -> Result<Transcript, IntcodeError> {
+        let mut text = String::new();
+        let mut answers = vec![];
+        let stop = loop {
+            match self.emulator.run_program()? {
+                RunSignal::Output(value) if is_ascii(value) => {
+                    text.push(value as u8 as char);
+                    if prompt.is_some_and(|prompt| text.ends_with(prompt)) {
+                        break Stop::Prompt;
+                    }
+                }
+                RunSignal::Output(value) => answers.push(value),
+                RunSignal::NoInput => break Stop::NoInput,
+                RunSignal::Halt => break Stop::Halt,
+                signal => break Stop::Debug(signal),
+            }
+        };
+        Ok(Transcript {
+            text,
+            answers,
+            stop,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ascii::*;
+    use crate::assembler::assemble;
+
+    // prompts for a line, echoes it back and then answers with its length * 1000
+    const ECHO: &str = "
+                out #62
+                out #32
+        read:   in -> [c]
+                eq [c], #10 -> [t]
+                jt [t], #done
+                out [c]
+                add [n], #1 -> [n]
+                jmp #read
+        done:   out #10
+                mul [n], #1000 -> [n]
+                out [n]
+                hlt
+        c:      db 0
+        n:      db 0
+        t:      db 0
+    ";
+
+    fn echo() -> Ascii {
+        Ascii::new(Emulator::new(assemble(ECHO).unwrap(), vec![], false))
+    }
+
+    #[test]
+    fn talks_in_text() {
+        let mut ascii = echo();
+        assert_eq!(
+            Transcript {
+                text: "> ".to_string(),
+                answers: vec![],
+                stop: Stop::NoInput
+            },
+            ascii.read().unwrap()
+        );
+        assert_eq!(
+            Transcript {
+                text: "hey\n".to_string(),
+                answers: vec![3000],
+                stop: Stop::Halt
+            },
+            ascii.command("hey").unwrap()
+        );
+    }
+
+    #[test]
+    fn stops_at_prompts() {
+        let mut ascii = echo();
+        ascii.send_line("abc\n").unwrap();
+        let transcript = ascii.read_until(">").unwrap();
+        assert_eq!(
+            (">".to_string(), Stop::Prompt),
+            (transcript.text, transcript.stop)
+        );
+        let transcript = ascii.read_until("b").unwrap();
+        assert_eq!(
+            (" ab".to_string(), Stop::Prompt),
+            (transcript.text, transcript.stop)
+        );
+        assert_eq!(vec![3000], ascii.read().unwrap().answers);
+    }
+
+    #[test]
+    fn sends_only_ascii() {
+        let mut ascii = echo();
+        ascii.send_line("ab\r\n").unwrap();
+        assert_eq!(vec![97, 98, 10], ascii.emulator.inputs);
+        assert_eq!(
+            Err(AsciiError::NotAscii {
+                position: 1,
+                character: 'é'
+            }),
+            ascii.command("né")
+        );
+        assert_eq!(vec![97, 98, 10], ascii.emulator.inputs);
+    }
+}
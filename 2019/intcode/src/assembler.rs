@@ -0,0 +1,577 @@
+use crate::intcode::*;
+use std::collections::HashMap;
+use std::fmt;
+
+/*
+    One statement per line, ';' starts a comment.
+
+        loop:   in -> [rb+1]            ; labels end with ':'
+                add [100], #5 -> [101]  ; reads, then the written parameter after '->'
+                jt #1, #loop            ; the '->' is optional, a plain comma works too
+        value:  db 1, 2, -3             ; raw words
+
+    Operands are '#imm', '[pos]' or '[rb+n]' and can use labels, e.g. '#loop' or '[value+1]'.
+    The disassembler's listing format ('0012: ADD [r+3], #5 -> [100]', 'DATA 7') is accepted too.
+
+    Macros built on the relative base, which is treated as a stack pointer:
+        mov a -> d      add a, #0 -> d
+        jmp a           jt #1, a
+        push a          add a, #0 -> [rb+0]; arb #1
+        pop -> d        arb #-1; add [rb+0], #0 -> d
+        call a          push the return address; jmp a
+        ret             arb #-1; jt #1, [rb+0]
+*/
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AssemblyErrorKind {
+    UnknownMnemonic(String),
+    WrongOperandCount { expected: usize, found: usize },
+    InvalidOperand(String),
+    InvalidLabel(String),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    WriteToImmediate,
+    // a sum of numbers and labels that doesn't fit in an i64
+    Overflow,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AssemblyError {
+    // 1-based, to match what an editor shows
+    pub line: usize,
+    pub kind: AssemblyErrorKind,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            AssemblyErrorKind::UnknownMnemonic(m) => write!(f, "unknown mnemonic '{}'", m),
+            AssemblyErrorKind::WrongOperandCount { expected, found } => {
+                write!(f, "expected {} operands, found {}", expected, found)
+            }
+            AssemblyErrorKind::InvalidOperand(o) => write!(f, "invalid operand '{}'", o),
+            AssemblyErrorKind::InvalidLabel(l) => write!(f, "invalid label '{}'", l),
+            AssemblyErrorKind::DuplicateLabel(l) => write!(f, "label '{}' defined twice", l),
+            AssemblyErrorKind::UndefinedLabel(l) => write!(f, "undefined label '{}'", l),
+            AssemblyErrorKind::WriteToImmediate => write!(f, "can't write to an immediate"),
+            AssemblyErrorKind::Overflow => write!(f, "value doesn't fit in 64 bits"),
+        }
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Term {
+    Number(i64),
+    Label(String),
+}
+
+// a sum of numbers and labels, resolved once every label has an address
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Expr {
+    terms: Vec<(i64, Term)>,
+}
+
+impl Expr {
+    fn number(value: i64) -> Expr {
+        Expr {
+            terms: vec![(1, Term::Number(value))],
+        }
+    }
+
+    fn label(name: &str) -> Expr {
+        Expr {
+            terms: vec![(1, Term::Label(name.to_string()))],
+        }
+    }
+
+    fn parse(text: &str) -> Option<Expr> {
+        let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+        if text.is_empty() {
+            return None;
+        }
+        let mut terms = vec![];
+        let mut sign = 1;
+        let mut current = String::new();
+        for (i, c) in text.chars().enumerate() {
+            if (c == '+' || c == '-') && i > 0 {
+                terms.push((sign, Expr::term(&current)?));
+                sign = if c == '-' { -1 } else { 1 };
+                current.clear();
+            } else {
+                current.push(c);
+            }
+        }
+        terms.push((sign, Expr::term(&current)?));
+        Some(Expr { terms })
+    }
+
+    fn term(text: &str) -> Option<Term> {
+        if let Ok(value) = text.parse::<i64>() {
+            return Some(Term::Number(value));
+        }
+        match is_identifier(text) {
+            true => Some(Term::Label(text.to_string())),
+            false => None,
+        }
+    }
+
+    fn resolve(&self, labels: &HashMap<String, usize>) -> Result<i64, AssemblyErrorKind> {
+        let mut total: i64 = 0;
+        for (sign, term) in &self.terms {
+            let value = match term {
+                Term::Number(value) => *value,
+                Term::Label(name) => match labels.get(name) {
+                    Some(address) => *address as i64,
+                    None => return Err(AssemblyErrorKind::UndefinedLabel(name.clone())),
+                },
+            };
+            total = sign
+                .checked_mul(value)
+                .and_then(|value| total.checked_add(value))
+                .ok_or(AssemblyErrorKind::Overflow)?;
+        }
+        Ok(total)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Operand {
+    mode: Mode,
+    value: Expr,
+}
+
+impl Operand {
+    fn immediate(value: Expr) -> Operand {
+        Operand {
+            mode: Mode::Immediate,
+            value,
+        }
+    }
+
+    fn relative(offset: i64) -> Operand {
+        Operand {
+            mode: Mode::Relative,
+            value: Expr::number(offset),
+        }
+    }
+
+    fn parse(text: &str) -> Option<Operand> {
+        let text = text.trim();
+        if let Some(value) = text.strip_prefix('#') {
+            return Some(Operand::immediate(Expr::parse(value)?));
+        }
+        let inner = text.strip_prefix('[')?.strip_suffix(']')?.trim();
+        for base in &["rb", "r"] {
+            if let Some(offset) = inner.strip_prefix(base) {
+                let offset = offset.trim_start();
+                if offset.is_empty() {
+                    return Some(Operand::relative(0));
+                }
+                if offset.starts_with('+') || offset.starts_with('-') {
+                    return Some(Operand {
+                        mode: Mode::Relative,
+                        value: Expr::parse(&format!("0{}", offset))?,
+                    });
+                }
+            }
+        }
+        Some(Operand {
+            mode: Mode::Position,
+            value: Expr::parse(inner)?,
+        })
+    }
+
+    fn mode_digit(&self) -> i64 {
+        match self.mode {
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Statement {
+    Instruction { opcode: i64, operands: Vec<Operand> },
+    Data(Vec<Expr>),
+}
+
+impl Statement {
+    fn len(&self) -> usize {
+        match self {
+            Statement::Instruction { operands, .. } => 1 + operands.len(),
+            Statement::Data(values) => values.len(),
+        }
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        }
+        _ => false,
+    }
+}
+
+fn opcode_for_mnemonic(mnemonic: &str) -> Option<i64> {
+    (1..=99).find(|opcode| match opcode_info(*opcode) {
+        Some(info) => info.mnemonic.eq_ignore_ascii_case(mnemonic),
+        None => false,
+    })
+}
+
+fn instruction(opcode: i64, operands: Vec<Operand>) -> Statement {
+    Statement::Instruction { opcode, operands }
+}
+
+fn parse_operands(text: &str) -> Result<Vec<Operand>, AssemblyErrorKind> {
+    // a lone destination ('in -> [x]') has nothing before its arrow
+    let text = text.trim();
+    let text = text.strip_prefix("->").unwrap_or(text).replace("->", ",");
+    if text.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    text.split(',')
+        .map(|o| {
+            Operand::parse(o).ok_or_else(|| AssemblyErrorKind::InvalidOperand(o.trim().to_string()))
+        })
+        .collect()
+}
+
+fn expect_operands(operands: &[Operand], expected: usize) -> Result<(), AssemblyErrorKind> {
+    match operands.len() == expected {
+        true => Ok(()),
+        false => Err(AssemblyErrorKind::WrongOperandCount {
+            expected,
+            found: operands.len(),
+        }),
+    }
+}
+
+// expand one source statement into the real instructions it stands for
+fn parse_statement(
+    mnemonic: &str,
+    rest: &str,
+    return_label: &str,
+) -> Result<Vec<Statement>, AssemblyErrorKind> {
+    let mnemonic = mnemonic.to_ascii_lowercase();
+    if mnemonic == "db" || mnemonic == "data" {
+        let values: Option<Vec<Expr>> = rest.split(',').map(Expr::parse).collect();
+        return match values {
+            Some(values) => Ok(vec![Statement::Data(values)]),
+            None => Err(AssemblyErrorKind::InvalidOperand(rest.trim().to_string())),
+        };
+    }
+    let mut operands = parse_operands(rest)?;
+    let zero = || Operand::immediate(Expr::number(0));
+    let one = || Operand::immediate(Expr::number(1));
+    let statements = match mnemonic.as_str() {
+        "mov" => {
+            expect_operands(&operands, 2)?;
+            operands.insert(1, zero());
+            vec![instruction(1, operands)]
+        }
+        "jmp" => {
+            expect_operands(&operands, 1)?;
+            operands.insert(0, one());
+            vec![instruction(5, operands)]
+        }
+        "push" => {
+            expect_operands(&operands, 1)?;
+            operands.push(zero());
+            operands.push(Operand::relative(0));
+            vec![instruction(1, operands), instruction(9, vec![one()])]
+        }
+        "pop" => {
+            expect_operands(&operands, 1)?;
+            let destination = operands.remove(0);
+            vec![
+                instruction(9, vec![Operand::immediate(Expr::number(-1))]),
+                instruction(1, vec![Operand::relative(0), zero(), destination]),
+            ]
+        }
+        "call" => {
+            expect_operands(&operands, 1)?;
+            let target = operands.remove(0);
+            let return_address = Operand::immediate(Expr::label(return_label));
+            vec![
+                instruction(1, vec![return_address, zero(), Operand::relative(0)]),
+                instruction(9, vec![one()]),
+                instruction(5, vec![one(), target]),
+            ]
+        }
+        "ret" => {
+            expect_operands(&operands, 0)?;
+            vec![
+                instruction(9, vec![Operand::immediate(Expr::number(-1))]),
+                instruction(5, vec![one(), Operand::relative(0)]),
+            ]
+        }
+        _ => {
+            let opcode = match opcode_for_mnemonic(&mnemonic) {
+                Some(opcode) => opcode,
+                None => return Err(AssemblyErrorKind::UnknownMnemonic(mnemonic)),
+            };
+            vec![instruction(opcode, operands)]
+        }
+    };
+    for statement in &statements {
+        if let Statement::Instruction { opcode, operands } = statement {
+            let info = opcode_info(*opcode).unwrap();
+            expect_operands(operands, info.parameters)?;
+            if let Some(index) = info.writes {
+                if operands[index - 1].mode == Mode::Immediate {
+                    return Err(AssemblyErrorKind::WriteToImmediate);
+                }
+            }
+        }
+    }
+    Ok(statements)
+}
+
+pub fn assemble(source: &str) -> Result<Vec<i64>, AssemblyError> {
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut statements: Vec<(usize, Statement)> = vec![];
+    let mut address = 0;
+
+    // first pass: work out where everything lives
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |kind| AssemblyError {
+            line: line_number,
+            kind,
+        };
+        let mut text = match line.find(';') {
+            Some(comment) => &line[..comment],
+            None => line,
+        }
+        .trim();
+
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if label.contains(char::is_whitespace) || label.contains('[') {
+                break;
+            }
+            // numeric labels are the addresses from a disassembler listing, nothing to define
+            if label.parse::<usize>().is_err() {
+                if !is_identifier(label) {
+                    return Err(error(AssemblyErrorKind::InvalidLabel(label.to_string())));
+                }
+                if labels.insert(label.to_string(), address).is_some() {
+                    return Err(error(AssemblyErrorKind::DuplicateLabel(label.to_string())));
+                }
+            }
+            text = text[colon + 1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = match text.find(char::is_whitespace) {
+            Some(space) => (&text[..space], &text[space..]),
+            None => (text, ""),
+        };
+        // macros that need to know where they end get a label no source can define
+        let return_label = format!("{}:return", line_number);
+        let expanded = parse_statement(mnemonic, rest, &return_label).map_err(error)?;
+        for statement in expanded {
+            address += statement.len();
+            statements.push((line_number, statement));
+        }
+        labels.insert(return_label, address);
+    }
+
+    // second pass: now the labels are known, emit the words
+    let mut program = vec![];
+    for (line, statement) in statements {
+        let error = |kind| AssemblyError { line, kind };
+        match statement {
+            Statement::Instruction { opcode, operands } => {
+                let mut instruction = opcode;
+                let mut parameters = vec![];
+                for (i, operand) in operands.iter().enumerate() {
+                    instruction += operand.mode_digit() * 10_i64.pow(i as u32 + 2);
+                    parameters.push(operand.value.resolve(&labels).map_err(error)?);
+                }
+                program.push(instruction);
+                program.append(&mut parameters);
+            }
+            Statement::Data(values) => {
+                for value in values {
+                    program.push(value.resolve(&labels).map_err(error)?);
+                }
+            }
+        }
+    }
+    Ok(program)
+}
+
+pub fn assemble_to_string(source: &str) -> Result<String, AssemblyError> {
+    Ok(common::vec_to_comma_separated_i64(assemble(source)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::*;
+    use crate::disassembler::*;
+
+    fn run(source: &str, inputs: Vec<i64>) -> Vec<i64> {
+        let mut emulator = Emulator::new(assemble(source).unwrap(), inputs, false);
+        loop {
+            match emulator.run_program().unwrap() {
+                RunSignal::Halt => break,
+                _ => continue,
+            }
+        }
+        emulator.outputs
+    }
+
+    #[test]
+    fn assemble_works() {
+        let source = "
+            ; double whatever comes in, until we see a zero
+            loop:   in -> [value]
+                    jf [value], #end
+                    mul [value], #2 -> [value]
+                    out [value]
+                    jt #1, #loop
+            end:    hlt
+            value:  db 0
+        ";
+        assert_eq!(
+            "3,15,1006,15,14,1002,15,2,15,4,15,1105,1,0,99,0",
+            assemble_to_string(source).unwrap()
+        );
+        assert_eq!(vec![2, 8, 10], run(source, vec![1, 4, 5, 0]));
+    }
+
+    #[test]
+    fn assemble_matches_hand_encoded_programs() {
+        assert_eq!(
+            "1002,4,3,4,33",
+            assemble_to_string("mul [4], #3, [4]\ndb 33").unwrap()
+        );
+        assert_eq!(
+            "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99",
+            assemble_to_string(
+                "
+                start:  arb #1
+                        out [rb-1]
+                        add [100], #1 -> [100]
+                        eq [100], #16 -> [101]
+                        jf [101], #start
+                        hlt
+                "
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn labels_can_be_offset() {
+        assert_eq!(
+            vec![4, 5, 104, 6, 99, 7, 8],
+            assemble("out [table]\nout #table+1\nhlt\ntable: db 7, 8").unwrap()
+        );
+        assert_eq!(vec![204, -2, 99], assemble("out [rb-2]\nhlt").unwrap());
+    }
+
+    #[test]
+    fn macros_use_the_relative_base_as_a_stack() {
+        let source = "
+                    arb #stack
+                    in -> [rb+0]
+                    arb #1
+                    call #double
+                    pop -> [result]
+                    out [result]
+                    hlt
+            ; doubles the value on top of the stack, in place
+            double: pop -> [scratch]
+                    pop -> [value]
+                    mul [value], #2 -> [value]
+                    push [value]
+                    push [scratch]
+                    ret
+            result: db 0
+            scratch: db 0
+            value:  db 0
+            stack:  db 0
+        ";
+        assert_eq!(vec![42], run(source, vec![21]));
+    }
+
+    #[test]
+    fn mov_and_jmp_expand() {
+        assert_eq!(
+            vec![1101, 7, 0, 5, 1105, 1, 0],
+            assemble("mov #7 -> [5]\njmp #0").unwrap()
+        );
+    }
+
+    #[test]
+    fn round_trips_through_the_disassembler() {
+        let programs = vec![
+            "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99",
+            "3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0",
+            "1102,34915192,34915192,7,4,7,99,0",
+            "42,11101,0,0,0,10099,-7,1,0,0",
+        ];
+        for program in programs {
+            let program = common::comma_separated_i64_to_vec(&program.to_string());
+            let listing = disassemble_to_string(&program);
+            assert_eq!(program, assemble(&listing).unwrap(), "{}", listing);
+        }
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        let error = assemble("hlt\nfoo #1").unwrap_err();
+        assert_eq!(2, error.line);
+        assert_eq!(
+            AssemblyErrorKind::UnknownMnemonic("foo".to_string()),
+            error.kind
+        );
+
+        assert_eq!(
+            AssemblyErrorKind::WrongOperandCount {
+                expected: 3,
+                found: 2
+            },
+            assemble("add #1, #2").unwrap_err().kind
+        );
+        assert_eq!(
+            AssemblyErrorKind::WriteToImmediate,
+            assemble("add #1, #2 -> #3").unwrap_err().kind
+        );
+        assert_eq!(
+            AssemblyErrorKind::InvalidOperand("5".to_string()),
+            assemble("out 5").unwrap_err().kind
+        );
+        assert_eq!(
+            AssemblyErrorKind::UndefinedLabel("nowhere".to_string()),
+            assemble("jmp #nowhere").unwrap_err().kind
+        );
+        assert_eq!(
+            AssemblyErrorKind::DuplicateLabel("a".to_string()),
+            assemble("a: hlt\na: hlt").unwrap_err().kind
+        );
+
+        let error = assemble("hlt\ndb 9223372036854775807+1").unwrap_err();
+        assert_eq!(2, error.line);
+        assert_eq!(AssemblyErrorKind::Overflow, error.kind);
+        assert_eq!(
+            AssemblyErrorKind::Overflow,
+            assemble("out #0-9223372036854775807-2").unwrap_err().kind
+        );
+        assert_eq!(
+            vec![-9223372036854775807 - 1],
+            assemble("db 0-9223372036854775807-1").unwrap()
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use common::*;
+use intcode::ascii::*;
+use intcode::intcode::*;
+use intcode::replay::*;
+use std::env;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+
+// play a text-driven intcode program from the terminal
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let record = match args.get(2).map(|s| s.as_str()) {
+        Some("--record") if args.len() == 4 => Some(args[3].clone()),
+        None if args.len() == 2 => None,
+        _ => {
+            println!("usage: ascii <program file> [--record <file>]");
+            return;
+        }
+    };
+    let program_spec = match file_to_vec(args[1].clone()) {
+        Ok(lines) => first_line(lines),
+        Err(error) => {
+            println!("couldn't read {}: {}", args[1], error);
+            return;
+        }
+    };
+    let mut ascii = Ascii::new(prepare_emulator(program_spec, "".to_string(), false));
+    ascii.emulator.set_recording(record.is_some());
+
+    let stdin = io::stdin();
+    loop {
+        let transcript = match ascii.read() {
+            Ok(transcript) => transcript,
+            Err(error) => {
+                println!("FAILED: {}", error);
+                break;
+            }
+        };
+        print!("{}", transcript.text);
+        for answer in transcript.answers {
+            println!("ANSWER: {}", answer);
+        }
+        if transcript.stop == Stop::Halt {
+            break;
+        }
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        if let Err(error) = ascii.send_line(&line) {
+            println!("{}", error);
+        }
+    }
+
+    if let (Some(path), Some(recording)) = (record, finish_recording(&ascii.emulator)) {
+        match save_recording(&recording, &path) {
+            Ok(()) => println!("recorded to {}", path),
+            Err(error) => println!("couldn't record to {}: {}", path, error),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use common::*;
+use intcode::debugger::*;
+use intcode::intcode::*;
+use std::env;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        println!("usage: debugger <program file> [comma separated inputs]");
+        return;
+    }
+    let program_spec = match file_to_vec(args[1].clone()) {
+        Ok(lines) => first_line(lines),
+        Err(error) => {
+            println!("couldn't read {}: {}", args[1], error);
+            return;
+        }
+    };
+    let input_spec = args.get(2).cloned().unwrap_or_default();
+    let mut debugger = Debugger::new(prepare_emulator(program_spec, input_spec, false));
+    println!("{}", debugger.current_instruction());
+
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        match debugger.execute(&line) {
+            Reply::Output(output) => println!("{}", output),
+            Reply::Quit => break,
+        }
+    }
+}
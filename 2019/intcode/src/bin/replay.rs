@@ -0,0 +1,35 @@
+use common::*;
+use intcode::intcode::*;
+use intcode::replay::*;
+use std::env;
+
+// check a program still does exactly what it did when a session with it was recorded
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        println!("usage: replay <program file> <recording>");
+        return;
+    }
+    let program_spec = match file_to_vec(args[1].clone()) {
+        Ok(lines) => first_line(lines),
+        Err(error) => {
+            println!("couldn't read {}: {}", args[1], error);
+            std::process::exit(1);
+        }
+    };
+    let result = load_recording(&args[2]).and_then(|recording| {
+        let emulator = prepare_emulator(program_spec, "".to_string(), false);
+        replay(emulator, &recording).map(|emulator| (recording, emulator))
+    });
+    match result {
+        Ok((recording, emulator)) => println!(
+            "OK: {} events over {} instructions",
+            recording.events.len(),
+            emulator.instructions_executed()
+        ),
+        Err(error) => {
+            println!("FAILED: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use common::*;
+use intcode::intcode::*;
+use intcode::trace::*;
+use intcode::word::BigWord;
+use std::env;
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+const USAGE: &str = "usage:
+    trace run <program file> [inputs] [filters]     stream a JSON Lines trace to stdout
+    trace filter <trace file> [filters]             keep the records that match
+    trace diff <trace file> <trace file>            show where two traces first differ
+
+filters:
+    --pcs <from>..<to>      only instructions at pcs in that range
+    --ops <op>,<op>...      only those opcodes, as numbers or mnemonics";
+
+fn parse_filter(args: &[String]) -> Result<TraceFilter, String> {
+    let mut filter = TraceFilter::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--pcs" => {
+                let bounds: Vec<&str> = value.split("..").collect();
+                let bounds: Vec<usize> = bounds
+                    .iter()
+                    .map(|bound| bound.trim_start_matches('=').parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("bad pc range {}", value))?;
+                match bounds.as_slice() {
+                    [from, to] => filter.pcs = Some(*from..=*to),
+                    _ => return Err(format!("bad pc range {}", value)),
+                }
+            }
+            "--ops" => {
+                let opcodes = value
+                    .split(',')
+                    .map(|op| match op.parse() {
+                        Ok(opcode) => Ok(opcode),
+                        Err(_) => (1..=99)
+                            .find(|opcode| {
+                                opcode_info(*opcode)
+                                    .is_some_and(|info| info.mnemonic.eq_ignore_ascii_case(op))
+                            })
+                            .ok_or_else(|| format!("unknown op {}", op)),
+                    })
+                    .collect::<Result<_, _>>()?;
+                filter.opcodes = Some(opcodes);
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(filter)
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let program_spec = first_line(file_to_vec(args[0].clone()).map_err(|e| e.to_string())?);
+    let (input_spec, filters) = match args.get(1) {
+        Some(inputs) if !inputs.starts_with("--") => (inputs.clone(), &args[2..]),
+        _ => ("".to_string(), &args[1..]),
+    };
+    let sink = Arc::new(Mutex::new(JsonLines::new(io::stdout())));
+    let tracer = Tracer::new(sink.clone()).with_filter(parse_filter(filters)?);
+    let mut emulator = prepare_emulator(program_spec, input_spec, false);
+    emulator.set_tracer(Some(tracer));
+    // outputs are in the trace, so just run until the program stops
+    let result = loop {
+        match emulator.run_program() {
+            Ok(RunSignal::Output(_)) => continue,
+            Ok(RunSignal::NoInput) => break Err("ran out of input".to_string()),
+            Ok(_) => break Ok(()),
+            Err(error) => break Err(error.to_string()),
+        }
+    };
+    if let Some(error) = sink.lock().unwrap().error.take() {
+        return Err(error.to_string());
+    }
+    result
+}
+
+// big words read traces written by an emulator of any word size
+fn filter(args: &[String]) -> Result<(), String> {
+    let records: Vec<TraceRecord<BigWord>> = load_trace(&args[0]).map_err(|e| e.to_string())?;
+    for record in parse_filter(&args[1..])?.apply(&records) {
+        println!("{}", record.to_json());
+    }
+    Ok(())
+}
+
+fn diff(args: &[String]) -> Result<(), String> {
+    let left: Vec<TraceRecord<BigWord>> = load_trace(&args[0]).map_err(|e| e.to_string())?;
+    let right: Vec<TraceRecord<BigWord>> =
+        load_trace(args.get(1).ok_or(USAGE)?).map_err(|e| e.to_string())?;
+    println!("{}", render_diff(&left, &right, 5));
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let result = match args.get(1).map(|s| s.as_str()) {
+        _ if args.len() < 3 => Err(USAGE.to_string()),
+        Some("run") => run(&args[2..]),
+        Some("filter") => filter(&args[2..]),
+        Some("diff") => diff(&args[2..]),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
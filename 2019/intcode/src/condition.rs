@@ -0,0 +1,336 @@
+use crate::intcode::*;
+use crate::word::Word;
+use std::fmt;
+
+/*
+    Expressions for conditional breakpoints, e.g. 'pc == 42 && mem[100] > 5'
+
+    pc, rb          the program counter and relative base
+    mem[expr]       a word of memory (0 outside memory)
+    inputs          how many inputs are queued
+    outputs         how many outputs have been produced
+
+    Operators, loosest first: || && (== != < <= > >=) (+ -) (* / %) (unary - !)
+    Comparisons and logic evaluate to 1 or 0, and anything non-zero counts as true.
+*/
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConditionError {
+    // byte offset into the expression where parsing gave up
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ConditionError {}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Variable {
+    Pc,
+    RelativeBase,
+    Inputs,
+    Outputs,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Node {
+    Number(i64),
+    Variable(Variable),
+    Memory(Box<Node>),
+    Negate(Box<Node>),
+    Not(Box<Node>),
+    Binary(String, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Condition {
+    source: String,
+    root: Node,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+// each level of precedence, loosest first
+const LEVELS: [&[&str]; 5] = [
+    &["||"],
+    &["&&"],
+    &["==", "!=", "<=", ">=", "<", ">"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+// how deep brackets, unary operators and chains of binary ones can go, so a long enough
+// condition can't overflow the stack parsing or evaluating it
+const MAX_DEPTH: usize = 200;
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: &str) -> Result<T, ConditionError> {
+        Err(ConditionError {
+            position: self.position,
+            message: message.to_string(),
+        })
+    }
+
+    // every call needs a matching 'self.depth -= 1' once what's nested has been parsed
+    fn nest(&mut self) -> Result<(), ConditionError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return self.error("nested too deeply");
+        }
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.text[self.position..].starts_with(token) {
+            self.position += token.len();
+            return true;
+        }
+        false
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Node, ConditionError> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        // each operator in a chain nests what came before it one deeper
+        let mut chained = 0;
+        'outer: loop {
+            for operator in LEVELS[level] {
+                if self.eat(operator) {
+                    self.nest()?;
+                    chained += 1;
+                    let right = self.binary(level + 1)?;
+                    left = Node::Binary(operator.to_string(), Box::new(left), Box::new(right));
+                    continue 'outer;
+                }
+            }
+            self.depth -= chained;
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, ConditionError> {
+        if self.eat("-") {
+            self.nest()?;
+            let value = self.unary()?;
+            self.depth -= 1;
+            return Ok(Node::Negate(Box::new(value)));
+        }
+        // careful not to eat the start of '!='
+        self.skip_whitespace();
+        if self.text[self.position..].starts_with('!')
+            && !self.text[self.position..].starts_with("!=")
+        {
+            self.position += 1;
+            self.nest()?;
+            let value = self.unary()?;
+            self.depth -= 1;
+            return Ok(Node::Not(Box::new(value)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Node, ConditionError> {
+        if self.eat("(") {
+            self.nest()?;
+            let node = self.binary(0)?;
+            if !self.eat(")") {
+                return self.error("expected ')'");
+            }
+            self.depth -= 1;
+            return Ok(node);
+        }
+        self.skip_whitespace();
+        let rest = &self.text[self.position..];
+        let length = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        let word = &rest[..length];
+        if word.is_empty() {
+            return self.error("expected a value");
+        }
+        if let Ok(value) = word.parse::<i64>() {
+            self.position += length;
+            return Ok(Node::Number(value));
+        }
+        let variable = match word {
+            "pc" => Variable::Pc,
+            "rb" => Variable::RelativeBase,
+            "inputs" => Variable::Inputs,
+            "outputs" => Variable::Outputs,
+            "mem" => {
+                self.position += length;
+                if !self.eat("[") {
+                    return self.error("expected '['");
+                }
+                self.nest()?;
+                let address = self.binary(0)?;
+                if !self.eat("]") {
+                    return self.error("expected ']'");
+                }
+                self.depth -= 1;
+                return Ok(Node::Memory(Box::new(address)));
+            }
+            _ => return self.error(&format!("unknown name '{}'", word)),
+        };
+        self.position += length;
+        Ok(Node::Variable(variable))
+    }
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, ConditionError> {
+        let mut parser = Parser {
+            text,
+            position: 0,
+            depth: 0,
+        };
+        let root = parser.binary(0)?;
+        parser.skip_whitespace();
+        if parser.position != text.len() {
+            return parser.error("unexpected trailing input");
+        }
+        Ok(Condition {
+            source: text.trim().to_string(),
+            root,
+        })
+    }
+
+    pub fn evaluate<W: Word>(&self, emulator: &Emulator<W>) -> i64 {
+        evaluate(&self.root, emulator)
+    }
+
+    pub fn is_met<W: Word>(&self, emulator: &Emulator<W>) -> bool {
+        self.evaluate(emulator) != 0
+    }
+}
+
+// words too wide for an i64 are clamped to it
+fn evaluate<W: Word>(node: &Node, emulator: &Emulator<W>) -> i64 {
+    match node {
+        Node::Number(value) => *value,
+        Node::Variable(Variable::Pc) => emulator.pc() as i64,
+        Node::Variable(Variable::RelativeBase) => emulator.relative_base() as i64,
+        Node::Variable(Variable::Inputs) => emulator.inputs.len() as i64,
+        Node::Variable(Variable::Outputs) => emulator.outputs.len() as i64,
+        Node::Memory(address) => match evaluate(address, emulator) {
+            address if address < 0 => 0,
+            address => emulator
+                .peek(address as usize)
+                .map_or(0, |word| word.saturating_i64()),
+        },
+        Node::Negate(value) => evaluate(value, emulator).wrapping_neg(),
+        Node::Not(value) => (evaluate(value, emulator) == 0) as i64,
+        Node::Binary(operator, left, right) => {
+            let left = evaluate(left, emulator);
+            // short circuit, so 'mem[x] ...' guarded by a check on x stays cheap
+            match operator.as_str() {
+                "&&" if left == 0 => return 0,
+                "||" if left != 0 => return 1,
+                _ => {}
+            }
+            let right = evaluate(right, emulator);
+            match operator.as_str() {
+                "||" | "&&" => (right != 0) as i64,
+                "==" => (left == right) as i64,
+                "!=" => (left != right) as i64,
+                "<" => (left < right) as i64,
+                "<=" => (left <= right) as i64,
+                ">" => (left > right) as i64,
+                ">=" => (left >= right) as i64,
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                "/" => left.checked_div(right).unwrap_or(0),
+                "%" => left.checked_rem(right).unwrap_or(0),
+                _ => unreachable!("unknown operator {}", operator),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::condition::*;
+
+    fn check(text: &str, emulator: &Emulator) -> i64 {
+        Condition::parse(text).unwrap().evaluate(emulator)
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let emulator = Emulator::new(vec![1, 2, 3, 10], vec![7], false);
+        assert_eq!(7, check("1 + 2 * 3", &emulator));
+        assert_eq!(9, check("(1 + 2) * 3", &emulator));
+        assert_eq!(1, check("pc == 0 && mem[3] > 5", &emulator));
+        assert_eq!(0, check("pc == 42 && mem[3] > 5", &emulator));
+        assert_eq!(1, check("pc == 42 || mem[mem[1]] == 3", &emulator));
+        assert_eq!(
+            1,
+            check("!(rb != 0) && inputs == 1 && outputs == 0", &emulator)
+        );
+        assert_eq!(-4, check("-mem[3] % 6", &emulator));
+        assert_eq!(0, check("mem[-1] + mem[100000] + 1 / 0", &emulator));
+        assert_eq!(1, check("1 <= 1 && 2 >= 1 && 1 < 2 && !0", &emulator));
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        assert_eq!(
+            ConditionError {
+                position: 0,
+                message: "unknown name 'bogus'".to_string()
+            },
+            Condition::parse("bogus == 1").unwrap_err()
+        );
+        assert_eq!(10, Condition::parse("mem[1 == 2").unwrap_err().position);
+        assert_eq!(3, Condition::parse("pc 5").unwrap_err().position);
+        assert!(Condition::parse("pc ==").is_err());
+        assert!(Condition::parse("").is_err());
+    }
+
+    #[test]
+    fn limits_how_deep_conditions_go() {
+        let emulator = Emulator::new(vec![5], vec![], false);
+        let nested = format!("{}1{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert_eq!(1, check(&nested, &emulator));
+        let chain = format!("mem[0]{}", " + 1".repeat(MAX_DEPTH));
+        assert_eq!(205, check(&chain, &emulator));
+
+        for text in &[
+            "(".repeat(10_000),
+            format!("{}1", "-".repeat(10_000)),
+            format!("{}1", "!".repeat(10_000)),
+            format!("{}0{}", "mem[".repeat(10_000), "]".repeat(10_000)),
+            format!("1{}", " * 1".repeat(10_000)),
+            format!("({}1{})", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH)),
+        ] {
+            assert_eq!(
+                "nested too deeply",
+                Condition::parse(text).unwrap_err().message
+            );
+        }
+    }
+}
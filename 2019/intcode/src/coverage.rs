@@ -0,0 +1,259 @@
+use crate::disassembler::*;
+use crate::intcode::*;
+use crate::opcodes::OpcodeTable;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/*
+    Which addresses a run executed as instructions, read as data and wrote to.
+    The listing follows the instructions that actually ran, so code reached by
+    jumping into what a plain disassembly took for data still lines up.
+
+        x.. 0000: IN -> [11]
+        ... 0008: OUT [12]      never reached
+        .rw 0011: DATA 0
+*/
+
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    // the address and length of each instruction executed
+    executed: BTreeMap<usize, usize>,
+    read: BTreeSet<usize>,
+    written: BTreeSet<usize>,
+    // (instruction, where it went) whenever one went anywhere but the next instruction,
+    // custom opcodes included
+    jumps: BTreeSet<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CoverageSummary {
+    pub instructions: usize,
+    pub instructions_executed: usize,
+    pub words: usize,
+    // executed as part of an instruction, read or written
+    pub words_used: usize,
+}
+
+fn percent(part: usize, whole: usize) -> f64 {
+    100.0 * part as f64 / whole.max(1) as f64
+}
+
+impl fmt::Display for CoverageSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} instructions executed ({:.1}%), {}/{} words used ({:.1}%)",
+            self.instructions_executed,
+            self.instructions,
+            percent(self.instructions_executed, self.instructions),
+            self.words_used,
+            self.words,
+            percent(self.words_used, self.words)
+        )
+    }
+}
+
+impl Coverage {
+    // an instruction that ran to completion at pc, which is now next_pc
+    pub fn record_instruction(
+        &mut self,
+        pc: usize,
+        instruction: i64,
+        next_pc: usize,
+        opcodes: &OpcodeTable,
+    ) {
+        let opcode = get_opcode(instruction);
+        let length = 1 + opcodes.info(opcode).map_or(0, |info| info.parameters);
+        self.executed.insert(pc, length);
+        // halting leaves the pc where it is, which isn't a jump
+        if opcode != 99 && next_pc != pc + length {
+            self.jumps.insert((pc, next_pc));
+        }
+    }
+
+    pub fn record_access(&mut self, address: usize, access: Access) {
+        match access {
+            Access::Read => self.read.insert(address),
+            Access::Write => self.written.insert(address),
+            Access::ReadWrite => self.read.insert(address) | self.written.insert(address),
+        };
+    }
+
+    // whether an instruction starting here was executed
+    pub fn is_executed(&self, address: usize) -> bool {
+        self.executed.contains_key(&address)
+    }
+
+    pub fn was_read(&self, address: usize) -> bool {
+        self.read.contains(&address)
+    }
+
+    pub fn was_written(&self, address: usize) -> bool {
+        self.written.contains(&address)
+    }
+
+    // everywhere the instruction at pc has jumped, which is the only way to know for one with a
+    // target that isn't immediate, or a custom opcode
+    pub fn jump_targets(&self, pc: usize) -> impl Iterator<Item = usize> + '_ {
+        self.jumps
+            .range((pc, 0)..=(pc, usize::MAX))
+            .map(|(_, to)| *to)
+    }
+
+    // is this word part of an instruction that was executed
+    fn in_executed(&self, address: usize) -> bool {
+        match self.executed.range(..=address).next_back() {
+            Some((start, length)) => address < start + length,
+            None => false,
+        }
+    }
+
+    // a disassembly of the program, decoding wherever an instruction ran as an instruction.
+    // The opcodes should be the emulator's, so any custom ones it ran are listed as code
+    pub fn listing(&self, program: &[i64], opcodes: &OpcodeTable) -> Vec<Instruction> {
+        let mut instructions = vec![];
+        let mut address = 0;
+        while address < program.len() {
+            let decoded =
+                decode_instruction_with(program, address, opcodes).filter(|instruction| {
+                    // don't let a guess at an instruction swallow the start of one that really ran
+                    self.is_executed(address)
+                        || !(address + 1..address + instruction.len()).any(|a| self.is_executed(a))
+                });
+            let instruction = decoded.unwrap_or_else(|| Instruction {
+                address,
+                words: vec![program[address]],
+                op: Op::Data(program[address]),
+            });
+            address += instruction.len();
+            instructions.push(instruction);
+        }
+        instructions
+    }
+
+    pub fn render(&self, program: &[i64], opcodes: &OpcodeTable) -> String {
+        let lines: Vec<String> = self
+            .listing(program, opcodes)
+            .iter()
+            .map(|instruction| {
+                let addresses = instruction.address..instruction.address + instruction.len();
+                let mark = |marked: bool, c: char| if marked { c } else { '.' };
+                format!(
+                    "{}{}{} {}",
+                    mark(self.is_executed(instruction.address), 'x'),
+                    mark(addresses.clone().any(|a| self.was_read(a)), 'r'),
+                    mark(addresses.clone().any(|a| self.was_written(a)), 'w'),
+                    instruction
+                )
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    pub fn summary(&self, program: &[i64], opcodes: &OpcodeTable) -> CoverageSummary {
+        let code: Vec<usize> = self
+            .listing(program, opcodes)
+            .iter()
+            .filter(|instruction| matches!(instruction.op, Op::Code { .. }))
+            .map(|instruction| instruction.address)
+            .collect();
+        CoverageSummary {
+            instructions: code.len(),
+            instructions_executed: code.iter().filter(|a| self.is_executed(**a)).count(),
+            words: program.len(),
+            words_used: (0..program.len())
+                .filter(|a| self.in_executed(*a) || self.was_read(*a) || self.was_written(*a))
+                .count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+    use crate::coverage::*;
+
+    const BRANCH: &str = "
+                in -> [x]
+                jt [x], #yes
+                out #0
+                hlt
+        yes:    out [value]
+                hlt
+        x:      db 0
+        value:  db 42
+    ";
+
+    fn covered(input: i64) -> (Coverage, Vec<i64>) {
+        let mut emulator = Emulator::new(assemble(BRANCH).unwrap(), vec![input], false);
+        emulator.set_coverage(true);
+        while emulator.run_program().unwrap() != RunSignal::Halt {}
+        (
+            emulator.coverage().unwrap().clone(),
+            emulator.program.to_vec(emulator.image()),
+        )
+    }
+
+    #[test]
+    fn renders_annotated_listings() {
+        let (coverage, program) = covered(0);
+        assert_eq!(
+            "\
+x.. 0000: IN -> [11]
+x.. 0002: JT [11], #8
+x.. 0005: OUT #0
+x.. 0007: HLT
+... 0008: OUT [12]
+... 0010: HLT
+.rw 0011: DATA 0
+... 0012: DATA 42",
+            coverage.render(&program, &OpcodeTable::new())
+        );
+        let summary = coverage.summary(&program, &OpcodeTable::new());
+        assert_eq!(
+            CoverageSummary {
+                instructions: 6,
+                instructions_executed: 4,
+                words: 13,
+                words_used: 9
+            },
+            summary
+        );
+        assert_eq!(
+            "4/6 instructions executed (66.7%), 9/13 words used (69.2%)",
+            summary.to_string()
+        );
+
+        // the jump wasn't taken, so it didn't go anywhere but on
+        assert_eq!(0, coverage.jump_targets(2).count());
+
+        let (coverage, _) = covered(1);
+        assert!(coverage.is_executed(8) && !coverage.is_executed(5));
+        assert_eq!(vec![8], coverage.jump_targets(2).collect::<Vec<usize>>());
+        assert!(coverage.was_read(12) && !coverage.was_written(12));
+    }
+
+    #[test]
+    fn follows_the_instructions_that_ran() {
+        // jt #1, #5 lands in the middle of what looks like ADD #4, #104 -> [99],
+        // and runs OUT #99; HLT from there
+        let program = vec![1105, 1, 5, 1101, 4, 104, 99, 99];
+        let mut emulator = Emulator::new(program.clone(), vec![], false);
+        emulator.set_coverage(true);
+        assert_eq!(RunSignal::Output(99), emulator.run_program().unwrap());
+        assert_eq!(RunSignal::Halt, emulator.run_program().unwrap());
+        assert_eq!(
+            "\
+x.. 0000: JT #1, #5
+... 0003: DATA 1101
+... 0004: DATA 4
+x.. 0005: OUT #99
+x.. 0007: HLT",
+            emulator
+                .coverage()
+                .unwrap()
+                .render(&program, emulator.opcodes())
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use crate::intcode::*;
+use std::fmt;
+
+/*
+    A network of emulators all running the same program, each starting with its own
+    inputs (phase settings and the like) and sending every output along its edges to
+    the inputs of other nodes. Chains, feedback loops, fan-out and fan-in all fall out
+    of which edges there are.
+
+    The nodes take turns on the calling thread, one signal each per round, so runs are
+    deterministic. The network stops as soon as the sink halts, or reports a deadlock if
+    a whole round goes by with every node still waiting on input that isn't coming.
+*/
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum DataflowError {
+    UnknownNode(usize),
+    Emulator { node: usize, error: IntcodeError },
+    // every node left running is waiting for input
+    Deadlock { waiting: Vec<usize> },
+    // the sink halted without outputting anything
+    NoSignal { sink: usize },
+}
+
+impl fmt::Display for DataflowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataflowError::UnknownNode(node) => write!(f, "there is no node {}", node),
+            DataflowError::Emulator { node, error } => write!(f, "node {} failed: {}", node, error),
+            DataflowError::Deadlock { waiting } => {
+                write!(f, "deadlock, nodes {:?} are all waiting for input", waiting)
+            }
+            DataflowError::NoSignal { sink } => {
+                write!(f, "node {} halted without any output", sink)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DataflowError {}
+
+#[derive(Debug, Clone)]
+pub struct Dataflow {
+    program: Vec<i64>,
+    debug: bool,
+    // the initial inputs of each node
+    nodes: Vec<Vec<i64>>,
+    // (from, to)
+    edges: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DataflowRun {
+    pub sink: usize,
+    // everything each node output, in order, whether or not anyone was listening
+    pub outputs: Vec<Vec<i64>>,
+    pub halted: Vec<bool>,
+}
+
+impl DataflowRun {
+    // the sink's final output
+    pub fn signal(&self) -> Option<i64> {
+        self.outputs[self.sink].last().copied()
+    }
+}
+
+impl Dataflow {
+    pub fn new(program: Vec<i64>, debug: bool) -> Dataflow {
+        Dataflow {
+            program,
+            debug,
+            nodes: vec![],
+            edges: vec![],
+        }
+    }
+
+    // amplifiers in a line, the first one seeded with a signal after its phase
+    pub fn chain(program: Vec<i64>, phases: &[i64], seed: i64, debug: bool) -> Dataflow {
+        let mut dataflow = Dataflow::new(program, debug);
+        for (i, phase) in phases.iter().enumerate() {
+            let node = match i {
+                0 => dataflow.add_node(vec![*phase, seed]),
+                _ => dataflow.add_node(vec![*phase]),
+            };
+            if node > 0 {
+                dataflow.connect(node - 1, node);
+            }
+        }
+        dataflow
+    }
+
+    // a chain with the last amplifier feeding back into the first
+    pub fn feedback_loop(program: Vec<i64>, phases: &[i64], seed: i64, debug: bool) -> Dataflow {
+        let mut dataflow = Dataflow::chain(program, phases, seed, debug);
+        if !phases.is_empty() {
+            dataflow.connect(phases.len() - 1, 0);
+        }
+        dataflow
+    }
+
+    pub fn add_node(&mut self, inputs: Vec<i64>) -> usize {
+        self.nodes.push(inputs);
+        self.nodes.len() - 1
+    }
+
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.edges.push((from, to));
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn run(&self, sink: usize) -> Result<DataflowRun, DataflowError> {
+        let unknown = std::iter::once(sink)
+            .chain(self.edges.iter().flat_map(|(from, to)| vec![*from, *to]))
+            .find(|node| *node >= self.nodes.len());
+        if let Some(node) = unknown {
+            return Err(DataflowError::UnknownNode(node));
+        }
+
+        let mut emulators: Vec<Emulator> = self
+            .nodes
+            .iter()
+            .map(|inputs| Emulator::new(self.program.clone(), inputs.clone(), self.debug))
+            .collect();
+        loop {
+            let mut progressed = false;
+            for node in 0..emulators.len() {
+                if emulators[node].is_halted() {
+                    continue;
+                }
+                let signal = emulators[node]
+                    .run_program()
+                    .map_err(|error| DataflowError::Emulator { node, error })?;
+                match signal {
+                    RunSignal::Halt if node == sink => {
+                        return Ok(DataflowRun {
+                            sink,
+                            outputs: emulators.iter().map(|e| e.outputs.clone()).collect(),
+                            halted: emulators.iter().map(|e| e.is_halted()).collect(),
+                        })
+                    }
+                    RunSignal::Halt => progressed = true,
+                    RunSignal::Output(value) => {
+                        for (_, to) in self.edges.iter().filter(|(from, _)| *from == node) {
+                            emulators[*to].inputs.push(value);
+                        }
+                        progressed = true;
+                    }
+                    // nothing to feed it until some other node outputs
+                    RunSignal::NoInput => {}
+                    // we never set any watchpoints, breakpoints or budgets
+                    RunSignal::Watch(_) | RunSignal::Breakpoint(_) | RunSignal::BudgetExhausted => {
+                        progressed = true
+                    }
+                }
+            }
+            if !progressed {
+                let waiting = (0..emulators.len())
+                    .filter(|node| !emulators[*node].is_halted())
+                    .collect();
+                return Err(DataflowError::Deadlock { waiting });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+    use crate::dataflow::*;
+
+    // (signal * 10) + phase
+    const SHIFT_AND_ADD: &str = "
+                in -> [phase]
+                in -> [signal]
+                mul [signal], #10 -> [signal]
+                add [signal], [phase] -> [phase]
+                out [phase]
+                hlt
+        phase:  db 0
+        signal: db 0
+    ";
+
+    // reads a phase, then adds it to every signal that comes through, halting after five
+    const ACCUMULATE: &str = "
+                in -> [phase]
+        loop:   in -> [signal]
+                add [signal], [phase] -> [signal]
+                out [signal]
+                add [count], #1 -> [count]
+                eq [count], #5 -> [done]
+                jf [done], #loop
+                hlt
+        phase:  db 0
+        signal: db 0
+        count:  db 0
+        done:   db 0
+    ";
+
+    #[test]
+    fn runs_chains() {
+        let dataflow =
+            Dataflow::chain(assemble(SHIFT_AND_ADD).unwrap(), &[4, 3, 2, 1, 0], 0, false);
+        let run = dataflow.run(4).unwrap();
+        assert_eq!(Some(43210), run.signal());
+        assert_eq!(vec![4], run.outputs[0]);
+        assert_eq!(vec![true; 5], run.halted);
+    }
+
+    #[test]
+    fn runs_feedback_loops() {
+        let dataflow = Dataflow::feedback_loop(assemble(ACCUMULATE).unwrap(), &[1, 2, 3], 0, false);
+        let run = dataflow.run(2).unwrap();
+        assert_eq!(Some(30), run.signal());
+        assert_eq!(vec![1, 7, 13, 19, 25], run.outputs[0]);
+    }
+
+    #[test]
+    fn runs_fan_out_and_fan_in() {
+        //     / 1 \
+        //   0      3
+        //     \ 2 /
+        let mut dataflow = Dataflow::new(assemble(SHIFT_AND_ADD).unwrap(), false);
+        let source = dataflow.add_node(vec![1, 0]);
+        let left = dataflow.add_node(vec![2]);
+        let right = dataflow.add_node(vec![3]);
+        let sink = dataflow.add_node(vec![]);
+        dataflow.connect(source, left);
+        dataflow.connect(source, right);
+        dataflow.connect(left, sink);
+        dataflow.connect(right, sink);
+        // the sink takes whichever arrives first as its phase
+        let run = dataflow.run(sink).unwrap();
+        assert_eq!(vec![vec![1], vec![12], vec![13], vec![142]], run.outputs);
+    }
+
+    #[test]
+    fn reports_problems() {
+        let mut dataflow = Dataflow::new(assemble(SHIFT_AND_ADD).unwrap(), false);
+        let first = dataflow.add_node(vec![1]);
+        let second = dataflow.add_node(vec![2]);
+        dataflow.connect(first, second);
+        assert_eq!(Err(DataflowError::UnknownNode(5)), dataflow.run(5));
+        assert_eq!(
+            Err(DataflowError::Deadlock {
+                waiting: vec![0, 1]
+            }),
+            dataflow.run(second)
+        );
+
+        let broken = Dataflow::chain(vec![1, 0, 0, 0, 42], &[1], 0, false);
+        match broken.run(0) {
+            Err(DataflowError::Emulator { node: 0, error }) => {
+                assert_eq!(ErrorKind::InvalidOpcode, error.kind)
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,777 @@
+use crate::condition::Condition;
+use crate::disassembler::*;
+use crate::flowgraph::FlowGraph;
+use crate::intcode::*;
+use crate::savefile;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Stop {
+    // executed an instruction and there's nothing to report
+    Stepped,
+    Breakpoint(usize),
+    Signal(RunSignal),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Registers {
+    pub pc: usize,
+    pub relative_base: usize,
+    pub halted: bool,
+    pub inputs: Vec<i64>,
+    pub outputs: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Reply {
+    Output(String),
+    Quit,
+}
+
+const HELP: &str = "\
+s, step [n]          execute n instructions (default 1)
+bs, back [n]         step back n instructions (default 1)
+back write <addr>    run back to the instruction that last wrote to an address
+back input           run back to just before the last input was taken
+history [n|off]      keep the last n instructions to step back over, or stop keeping them
+c, continue          run until a breakpoint, output, input request or halt
+b, break [addr]      set a breakpoint, or list them
+d, delete <addr>     remove a breakpoint
+r, regs              show pc, relative base and pending I/O
+x, peek <addr> [n]   show n words of memory (default 1)
+poke <addr> <value>  overwrite a word of memory
+i, input <v>...      queue input values
+w, watch <addr> [end] [r|w|rw]
+                     stop when memory is read and/or written (default rw)
+when <expr>          stop when an expression holds, e.g. 'when pc == 42 && mem[100] > 5'
+u, unwatch <id>      remove a watchpoint or conditional breakpoint
+save <file>          save the machine state to a file
+load <file>          replace the machine state with one from a file
+profile [on|off]     start or stop profiling, or show the profile so far
+coverage [on|off]    start or stop recording coverage, or show the annotated listing
+cfg [file]           show the control-flow graph as DOT, or write it to a file,
+                     with any jumps coverage has seen filled in
+l, list [addr] [n]   disassemble n instructions (default 10 from pc)
+q, quit              leave the debugger";
+
+// how many instructions can be stepped back over unless the history is resized
+pub const DEFAULT_HISTORY: usize = 100_000;
+
+// the most words or instructions shown at once, since memory can reach up to 2^64
+pub const MAX_LISTING: usize = 10_000;
+
+pub struct Debugger {
+    pub emulator: Emulator,
+    breakpoints: BTreeSet<usize>,
+}
+
+impl Debugger {
+    pub fn new(emulator: Emulator) -> Debugger {
+        let mut emulator = emulator;
+        emulator.set_undo_log(Some(DEFAULT_HISTORY));
+        Debugger {
+            emulator,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.insert(pc)
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn breakpoints(&self) -> Vec<usize> {
+        self.breakpoints.iter().copied().collect()
+    }
+
+    pub fn step(&mut self) -> Result<Stop, IntcodeError> {
+        match self.emulator.step()? {
+            Some(signal) => Ok(Stop::Signal(signal)),
+            None => Ok(Stop::Stepped),
+        }
+    }
+
+    pub fn continue_execution(&mut self) -> Result<Stop, IntcodeError> {
+        // always execute the first instruction, otherwise we'd never get off a breakpoint
+        let mut first = true;
+        loop {
+            if !first && self.breakpoints.contains(&self.emulator.pc()) {
+                return Ok(Stop::Breakpoint(self.emulator.pc()));
+            }
+            first = false;
+            match self.step()? {
+                Stop::Stepped => continue,
+                stop => return Ok(stop),
+            }
+        }
+    }
+
+    // how many instructions were actually stepped back over, which is fewer if the history ran out
+    pub fn step_back(&mut self, count: usize) -> usize {
+        (0..count)
+            .take_while(|_| self.emulator.step_back().is_some())
+            .count()
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            pc: self.emulator.pc(),
+            relative_base: self.emulator.relative_base(),
+            halted: self.emulator.is_halted(),
+            inputs: self.emulator.inputs.clone(),
+            outputs: self.emulator.outputs.clone(),
+        }
+    }
+
+    pub fn peek(&self, address: usize, count: usize) -> Vec<i64> {
+        (address..address.saturating_add(count.min(MAX_LISTING)))
+            .map_while(|a| self.emulator.peek(a))
+            .collect()
+    }
+
+    pub fn poke(&mut self, address: usize, value: i64) -> bool {
+        self.emulator.poke(address, value)
+    }
+
+    pub fn push_input(&mut self, value: i64) {
+        self.emulator.inputs.push(value);
+    }
+
+    pub fn current_instruction(&self) -> Instruction {
+        self.listing(self.emulator.pc(), 1).remove(0)
+    }
+
+    // disassemble from an address, which unlike a whole-program listing can start mid-program
+    pub fn listing(&self, address: usize, count: usize) -> Vec<Instruction> {
+        let memory = &self.emulator.program;
+        let mut instructions = vec![];
+        let mut address = address;
+        while instructions.len() < count.min(MAX_LISTING) {
+            let words: Vec<i64> = (address..address.saturating_add(MAX_INSTRUCTION_LENGTH))
+                .map_while(|a| memory.get(a))
+                .collect();
+            let instruction = match decode_instruction_with(&words, 0, self.emulator.opcodes()) {
+                Some(instruction) => Instruction {
+                    address,
+                    ..instruction
+                },
+                None => {
+                    let value = memory.get(address).unwrap_or(0);
+                    Instruction {
+                        address,
+                        words: vec![value],
+                        op: Op::Data(value),
+                    }
+                }
+            };
+            address += instruction.len();
+            instructions.push(instruction);
+        }
+        instructions
+    }
+
+    // the words the program was loaded with, as they are now. A far write can put the rest of
+    // memory anywhere up to 2^64, so that's left out
+    fn image(&self) -> Vec<i64> {
+        self.emulator.program.to_vec(self.emulator.image())
+    }
+
+    // of memory as it is now, which is what will run from here on
+    fn flow_graph(&self) -> FlowGraph {
+        let program = self.image();
+        FlowGraph::build(&program, self.emulator.opcodes(), self.emulator.coverage())
+    }
+
+    fn watch(&mut self, args: &[&str], access: Access) -> Reply {
+        let addresses: Result<Vec<usize>, _> = args.iter().map(|a| a.parse::<usize>()).collect();
+        let (start, end) = match addresses.as_deref() {
+            Ok([start]) => (*start, *start),
+            Ok([start, end]) if start <= end => (*start, *end),
+            _ => return Reply::Output(format!("error: bad arguments {:?}", args)),
+        };
+        let id = self.emulator.add_watchpoint(start..=end, access);
+        Reply::Output(format!("watchpoint {} set on {}..={}", id, start, end))
+    }
+
+    fn describe(&self, stop: Result<Stop, IntcodeError>) -> String {
+        match stop {
+            Ok(Stop::Stepped) => self.current_instruction().to_string(),
+            Ok(Stop::Breakpoint(pc)) => {
+                format!("breakpoint at {}\n{}", pc, self.current_instruction())
+            }
+            Ok(Stop::Signal(RunSignal::Output(value))) => format!("output {}", value),
+            Ok(Stop::Signal(RunSignal::NoInput)) => {
+                format!("waiting for input\n{}", self.current_instruction())
+            }
+            Ok(Stop::Signal(RunSignal::Halt)) => "halted".to_string(),
+            Ok(Stop::Signal(RunSignal::Watch(hit))) => {
+                let (verb, preposition) = match hit.access {
+                    Access::Write => ("wrote", "to"),
+                    _ => ("read", "from"),
+                };
+                format!(
+                    "watchpoint {}: {} {} {} {} at pc {}\n{}",
+                    hit.watchpoint,
+                    verb,
+                    hit.value,
+                    preposition,
+                    hit.address,
+                    hit.pc,
+                    self.current_instruction()
+                )
+            }
+            Ok(Stop::Signal(RunSignal::Breakpoint(id))) => {
+                format!("breakpoint {} hit\n{}", id, self.current_instruction())
+            }
+            Ok(Stop::Signal(RunSignal::BudgetExhausted)) => {
+                format!("instruction budget used up\n{}", self.current_instruction())
+            }
+            Err(error) => format!("error: {}", error),
+        }
+    }
+
+    // run a line of REPL input
+    pub fn execute(&mut self, line: &str) -> Reply {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Reply::Output(String::new()),
+        };
+        match (command, args) {
+            ("when", []) => {
+                let conditions: Vec<String> = self
+                    .emulator
+                    .breakpoints()
+                    .iter()
+                    .map(|b| format!("{}: {}", b.id, b.condition))
+                    .collect();
+                return Reply::Output(conditions.join("\n"));
+            }
+            ("when", _) => {
+                let expression = line.trim()["when".len()..].trim();
+                return Reply::Output(match Condition::parse(expression) {
+                    Ok(condition) => {
+                        format!("breakpoint {} set", self.emulator.add_breakpoint(condition))
+                    }
+                    Err(error) => format!("error: {}", error),
+                });
+            }
+            ("w", [.., "r"]) | ("watch", [.., "r"]) => {
+                return self.watch(&args[..args.len() - 1], Access::Read)
+            }
+            ("w", [.., "w"]) | ("watch", [.., "w"]) => {
+                return self.watch(&args[..args.len() - 1], Access::Write)
+            }
+            ("w", [.., "rw"]) | ("watch", [.., "rw"]) => {
+                return self.watch(&args[..args.len() - 1], Access::ReadWrite)
+            }
+            ("w", _) | ("watch", _) => return self.watch(args, Access::ReadWrite),
+            ("save", [path]) => {
+                return Reply::Output(match savefile::save(&self.emulator, path) {
+                    Ok(()) => format!("saved to {}", path),
+                    Err(error) => format!("error: {}", error),
+                })
+            }
+            ("profile", ["on"]) | ("profile", ["off"]) => {
+                self.emulator.set_profiling(args[0] == "on");
+                return Reply::Output(format!("profiling {}", args[0]));
+            }
+            ("profile", []) => {
+                return Reply::Output(match self.emulator.profile() {
+                    Some(profile) => profile.report(10).trim_end().to_string(),
+                    None => "profiling is off, 'profile on' to start".to_string(),
+                })
+            }
+            ("coverage", ["on"]) | ("coverage", ["off"]) => {
+                self.emulator.set_coverage(args[0] == "on");
+                return Reply::Output(format!("coverage {}", args[0]));
+            }
+            ("coverage", []) => {
+                let program = self.image();
+                return Reply::Output(match self.emulator.coverage() {
+                    Some(coverage) => {
+                        format!(
+                            "{}\n{}",
+                            coverage.render(&program, self.emulator.opcodes()),
+                            coverage.summary(&program, self.emulator.opcodes())
+                        )
+                    }
+                    None => "coverage is off, 'coverage on' to start".to_string(),
+                });
+            }
+            ("cfg", []) => return Reply::Output(self.flow_graph().to_dot()),
+            ("cfg", [path]) => {
+                let graph = self.flow_graph();
+                return Reply::Output(match std::fs::write(path, graph.to_dot()) {
+                    Ok(()) => format!("wrote {} blocks to {}", graph.blocks().count(), path),
+                    Err(error) => format!("error: {}", error),
+                });
+            }
+            ("back", ["input"]) => {
+                return Reply::Output(match self.emulator.back_to_input() {
+                    Some(steps) => format!(
+                        "back {} to before input {}\n{}",
+                        steps,
+                        self.emulator.inputs[0],
+                        self.current_instruction()
+                    ),
+                    None => "no input in the history".to_string(),
+                })
+            }
+            ("back", ["write", address]) => {
+                let address = match address.parse::<usize>() {
+                    Ok(address) => address,
+                    Err(_) => return Reply::Output(format!("error: bad address {}", address)),
+                };
+                return Reply::Output(match self.emulator.back_to_write(address) {
+                    Some(steps) => format!(
+                        "back {} to the last write to {}\n{}",
+                        steps,
+                        address,
+                        self.current_instruction()
+                    ),
+                    None => format!("no write to {} in the history", address),
+                });
+            }
+            ("history", ["off"]) => {
+                self.emulator.set_undo_log(None);
+                return Reply::Output("history off".to_string());
+            }
+            ("history", []) => {
+                return Reply::Output(match self.emulator.undo_log() {
+                    Some(log) => format!(
+                        "{} of the last {} instructions can be stepped back over",
+                        log.len(),
+                        log.capacity()
+                    ),
+                    None => "history is off, 'history <n>' to start".to_string(),
+                })
+            }
+            ("load", [path]) => {
+                let snapshot = savefile::load_snapshot(path);
+                return Reply::Output(match snapshot {
+                    Ok(snapshot) => {
+                        self.emulator.restore(&snapshot);
+                        format!("loaded {}\n{}", path, self.current_instruction())
+                    }
+                    Err(error) => format!("error: {}", error),
+                });
+            }
+            _ => {}
+        }
+        let numbers: Result<Vec<i64>, _> = args.iter().map(|a| a.parse::<i64>()).collect();
+        let numbers = match numbers {
+            Ok(numbers) => numbers,
+            Err(_) => return Reply::Output(format!("error: bad arguments {:?}", args)),
+        };
+        // everything but input values and the value to poke is an address or a count
+        let unsigned = match command {
+            "i" | "input" => 0,
+            "poke" => 1,
+            _ => numbers.len(),
+        };
+        let addresses: Result<Vec<usize>, _> = numbers[..unsigned.min(numbers.len())]
+            .iter()
+            .map(|n| usize::try_from(*n).map_err(|_| n))
+            .collect();
+        let addresses = match addresses {
+            Ok(addresses) => addresses,
+            Err(n) => return Reply::Output(format!("error: {} can't be negative", n)),
+        };
+        let address = |i: usize| addresses.get(i).copied();
+        let shown = matches!(command, "x" | "peek" | "l" | "list");
+        if shown && address(1).is_some_and(|count| count > MAX_LISTING) {
+            return Reply::Output(format!(
+                "error: can't show more than {} at once",
+                MAX_LISTING
+            ));
+        }
+
+        let output = match (command, numbers.len()) {
+            ("s", _) | ("step", _) => {
+                let mut output = vec![];
+                for _ in 0..address(0).unwrap_or(1) {
+                    let stop = self.step();
+                    let finished = !matches!(stop, Ok(Stop::Stepped));
+                    if finished {
+                        output.push(self.describe(stop));
+                        break;
+                    }
+                }
+                if output.is_empty() {
+                    output.push(self.describe(Ok(Stop::Stepped)));
+                }
+                output.join("\n")
+            }
+            ("bs", n) | ("back", n) if n <= 1 => {
+                let count = address(0).unwrap_or(1);
+                match self.step_back(count) {
+                    steps if steps < count => format!(
+                        "back {} to the start of the history\n{}",
+                        steps,
+                        self.current_instruction()
+                    ),
+                    _ => self.current_instruction().to_string(),
+                }
+            }
+            ("history", 1) => {
+                self.emulator.set_undo_log(address(0));
+                format!("keeping the last {} instructions", numbers[0])
+            }
+            ("c", 0) | ("continue", 0) => {
+                let stop = self.continue_execution();
+                self.describe(stop)
+            }
+            ("b", 0) | ("break", 0) => format!("breakpoints: {:?}", self.breakpoints()),
+            ("b", 1) | ("break", 1) => {
+                self.add_breakpoint(address(0).unwrap());
+                format!("breakpoint set at {}", numbers[0])
+            }
+            ("d", 1) | ("delete", 1) => match self.remove_breakpoint(address(0).unwrap()) {
+                true => format!("breakpoint removed from {}", numbers[0]),
+                false => format!("no breakpoint at {}", numbers[0]),
+            },
+            ("r", 0) | ("regs", 0) => {
+                let registers = self.registers();
+                format!(
+                    "pc {} rb {}{}\ninputs {:?}\noutputs {:?}",
+                    registers.pc,
+                    registers.relative_base,
+                    if registers.halted { " (halted)" } else { "" },
+                    registers.inputs,
+                    registers.outputs
+                )
+            }
+            ("x", 1) | ("x", 2) | ("peek", 1) | ("peek", 2) => {
+                let start = address(0).unwrap();
+                let words = self.peek(start, address(1).unwrap_or(1));
+                let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+                format!("{:04}: {}", start, words.join(" "))
+            }
+            ("poke", 2) => match self.poke(address(0).unwrap(), numbers[1]) {
+                true => format!("{:04}: {}", numbers[0], numbers[1]),
+                false => format!("error: {} is outside memory", numbers[0]),
+            },
+            ("i", _) | ("input", _) => {
+                for value in &numbers {
+                    self.push_input(*value);
+                }
+                format!("inputs {:?}", self.emulator.inputs)
+            }
+            ("l", n) | ("list", n) if n <= 2 => {
+                let start = address(0).unwrap_or_else(|| self.emulator.pc());
+                render(&self.listing(start, address(1).unwrap_or(10)))
+            }
+            ("u", 1) | ("unwatch", 1) => {
+                let id = address(0).unwrap();
+                match self.emulator.remove_watchpoint(id) || self.emulator.remove_breakpoint(id) {
+                    true => format!("removed {}", id),
+                    false => format!("nothing to remove with id {}", id),
+                }
+            }
+            ("q", 0) | ("quit", 0) => return Reply::Quit,
+            ("h", _) | ("help", _) => HELP.to_string(),
+            _ => format!("error: unknown command '{}', try 'help'", line.trim()),
+        };
+        Reply::Output(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::*;
+    use crate::debugger::*;
+
+    const COUNTDOWN: &str = "
+                in -> [count]
+        loop:   out [count]
+                add [count], #-1 -> [count]
+                jt [count], #loop
+                hlt
+        count:  db 0
+    ";
+
+    fn debugger(source: &str, inputs: Vec<i64>) -> Debugger {
+        Debugger::new(Emulator::new(assemble(source).unwrap(), inputs, false))
+    }
+
+    fn reply(debugger: &mut Debugger, line: &str) -> String {
+        match debugger.execute(line) {
+            Reply::Output(output) => output,
+            Reply::Quit => panic!("unexpected quit"),
+        }
+    }
+
+    #[test]
+    fn steps_one_instruction_at_a_time() {
+        let mut debugger = debugger(COUNTDOWN, vec![2]);
+        assert_eq!(Stop::Stepped, debugger.step().unwrap());
+        assert_eq!(2, debugger.registers().pc);
+        assert_eq!(Stop::Signal(RunSignal::Output(2)), debugger.step().unwrap());
+        assert_eq!(4, debugger.registers().pc);
+        assert_eq!(vec![2], debugger.registers().outputs);
+    }
+
+    #[test]
+    fn continue_stops_at_breakpoints() {
+        let mut debugger = debugger(COUNTDOWN, vec![3]);
+        assert!(debugger.add_breakpoint(8));
+        assert_eq!(
+            Stop::Signal(RunSignal::Output(3)),
+            debugger.continue_execution().unwrap()
+        );
+        assert_eq!(Stop::Breakpoint(8), debugger.continue_execution().unwrap());
+        assert_eq!(2, debugger.peek(12, 1)[0]);
+        // resuming from a breakpoint doesn't immediately hit it again
+        assert_eq!(
+            Stop::Signal(RunSignal::Output(2)),
+            debugger.continue_execution().unwrap()
+        );
+        assert!(debugger.remove_breakpoint(8));
+        assert_eq!(
+            Stop::Signal(RunSignal::Output(1)),
+            debugger.continue_execution().unwrap()
+        );
+        assert_eq!(
+            Stop::Signal(RunSignal::Halt),
+            debugger.continue_execution().unwrap()
+        );
+        assert!(debugger.registers().halted);
+    }
+
+    #[test]
+    fn memory_can_be_peeked_and_poked() {
+        let mut debugger = debugger(COUNTDOWN, vec![5]);
+        debugger.step().unwrap();
+        assert_eq!(vec![99, 5], debugger.peek(11, 2));
+        assert!(debugger.poke(12, 1));
+        debugger.continue_execution().unwrap();
+        assert_eq!(
+            Stop::Signal(RunSignal::Halt),
+            debugger.continue_execution().unwrap()
+        );
+        assert_eq!(vec![1], debugger.registers().outputs);
+        assert!(debugger.poke(1 << 40, 1));
+        assert_eq!(vec![1], debugger.peek(1 << 40, 1));
+        debugger.emulator.set_memory_limit(Some(100));
+        assert!(!debugger.poke(100, 1));
+        assert_eq!(vec![0, 0], debugger.peek(98, 5));
+    }
+
+    #[test]
+    fn repl_commands_work() {
+        let mut debugger = debugger(COUNTDOWN, vec![]);
+        assert_eq!(
+            "waiting for input\n0000: IN -> [12]",
+            reply(&mut debugger, "c")
+        );
+        assert_eq!("inputs [2]", reply(&mut debugger, "i 2"));
+        assert_eq!("breakpoint set at 8", reply(&mut debugger, "b 8"));
+        assert_eq!("breakpoints: [8]", reply(&mut debugger, "break"));
+        assert_eq!("output 2", reply(&mut debugger, "continue"));
+        assert_eq!(
+            "breakpoint at 8\n0008: JT [12], #2",
+            reply(&mut debugger, "c")
+        );
+        assert_eq!(
+            "pc 8 rb 0\ninputs []\noutputs [2]",
+            reply(&mut debugger, "regs")
+        );
+        assert_eq!("0012: 1", reply(&mut debugger, "x 12"));
+        assert_eq!("0012: 0", reply(&mut debugger, "poke 12 0"));
+        assert_eq!(
+            "0008: JT [12], #2\n0011: HLT",
+            reply(&mut debugger, "l 8 2")
+        );
+        assert_eq!("halted", reply(&mut debugger, "s 5"));
+        assert!(reply(&mut debugger, "bogus").starts_with("error"));
+        assert!(reply(&mut debugger, "x nope").starts_with("error"));
+        assert_eq!(Reply::Quit, debugger.execute("q"));
+    }
+
+    #[test]
+    fn repl_profiles() {
+        let mut debugger = debugger(COUNTDOWN, vec![1]);
+        assert!(reply(&mut debugger, "profile").starts_with("profiling is off"));
+        assert_eq!("profiling on", reply(&mut debugger, "profile on"));
+        assert_eq!("output 1", reply(&mut debugger, "s 3"));
+        assert!(reply(&mut debugger, "profile").starts_with("instructions retired: 2\n"));
+        assert_eq!("profiling off", reply(&mut debugger, "profile off"));
+        assert!(debugger.emulator.profile().is_none());
+    }
+
+    #[test]
+    fn repl_steps_back() {
+        let mut debugger = debugger(COUNTDOWN, vec![2]);
+        assert_eq!("output 2", reply(&mut debugger, "c"));
+        assert_eq!("output 1", reply(&mut debugger, "c"));
+        assert_eq!(
+            "back 3 to the last write to 12\n0004: ADD [12], #-1 -> [12]",
+            reply(&mut debugger, "back write 12")
+        );
+        assert_eq!(vec![2], debugger.peek(12, 1));
+        assert_eq!("0002: OUT [12]", reply(&mut debugger, "bs"));
+        assert_eq!(
+            "back 1 to before input 2\n0000: IN -> [12]",
+            reply(&mut debugger, "back input")
+        );
+        assert_eq!(
+            "back 0 to the start of the history\n0000: IN -> [12]",
+            reply(&mut debugger, "back")
+        );
+        assert_eq!(
+            "0 of the last 100000 instructions can be stepped back over",
+            reply(&mut debugger, "history")
+        );
+        assert_eq!("output 2", reply(&mut debugger, "c"));
+        assert_eq!("history off", reply(&mut debugger, "history off"));
+        assert_eq!(
+            "no input in the history",
+            reply(&mut debugger, "back input")
+        );
+        assert_eq!(
+            "keeping the last 5 instructions",
+            reply(&mut debugger, "history 5")
+        );
+    }
+
+    #[test]
+    fn repl_rejects_negative_addresses_and_counts() {
+        let mut debugger = debugger(COUNTDOWN, vec![1]);
+        assert_eq!("error: -1 can't be negative", reply(&mut debugger, "s -1"));
+        assert_eq!(
+            "error: -1 can't be negative",
+            reply(&mut debugger, "poke -1 5")
+        );
+        assert_eq!(
+            "error: -3 can't be negative",
+            reply(&mut debugger, "x 0 -3")
+        );
+        assert_eq!(13, debugger.emulator.program.high_water_mark());
+        assert_eq!(
+            "error: can't show more than 10000 at once",
+            reply(&mut debugger, "x 0 1000000000000")
+        );
+        assert_eq!(
+            "error: can't show more than 10000 at once",
+            reply(&mut debugger, "l 0 10001")
+        );
+        assert_eq!(MAX_LISTING, debugger.peek(0, usize::MAX).len());
+        // values can be anything
+        assert_eq!("0012: -5", reply(&mut debugger, "poke 12 -5"));
+        assert_eq!("inputs [1, -2]", reply(&mut debugger, "i -2"));
+    }
+
+    #[test]
+    fn repl_shows_coverage() {
+        let mut debugger = debugger(COUNTDOWN, vec![1]);
+        assert!(reply(&mut debugger, "coverage").starts_with("coverage is off"));
+        assert_eq!("coverage on", reply(&mut debugger, "coverage on"));
+        assert_eq!("output 1", reply(&mut debugger, "c"));
+        // only the program is listed, not everything up to a far write
+        assert_eq!(
+            "1099511627776: 1",
+            reply(&mut debugger, "poke 1099511627776 1")
+        );
+        let coverage = reply(&mut debugger, "coverage");
+        assert!(coverage.starts_with("x.. 0000: IN -> [12]\nx.. 0002: OUT [12]\n"));
+        assert!(coverage.ends_with("2/5 instructions executed (40.0%), 5/13 words used (38.5%)"));
+    }
+
+    #[test]
+    fn repl_exports_the_flow_graph() {
+        let mut debugger = debugger(COUNTDOWN, vec![1]);
+        assert_eq!(
+            "1099511627776: 1",
+            reply(&mut debugger, "poke 1099511627776 1")
+        );
+        let dot = reply(&mut debugger, "cfg");
+        assert!(dot.starts_with("digraph program {"));
+        assert!(dot.contains("    b2 -> b2 [label=\"jump\"];\n    b2 -> b11;\n"));
+
+        let path = std::env::temp_dir().join(format!("debugger-{}.dot", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(
+            format!("wrote 3 blocks to {}", path),
+            reply(&mut debugger, &format!("cfg {}", path))
+        );
+        assert_eq!(dot, std::fs::read_to_string(path).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn repl_watches_memory() {
+        let mut debugger = debugger(COUNTDOWN, vec![2]);
+        assert_eq!(
+            "watchpoint 1 set on 12..=12",
+            reply(&mut debugger, "watch 12 w")
+        );
+        assert_eq!(
+            "watchpoint 1: wrote 2 to 12 at pc 0\n0002: OUT [12]",
+            reply(&mut debugger, "c")
+        );
+        assert_eq!("output 2", reply(&mut debugger, "c"));
+        assert_eq!(
+            "watchpoint 1: wrote 1 to 12 at pc 4\n0008: JT [12], #2",
+            reply(&mut debugger, "c")
+        );
+        assert_eq!("removed 1", reply(&mut debugger, "u 1"));
+        assert_eq!(
+            "watchpoint 2 set on 10..=20",
+            reply(&mut debugger, "w 10 20 r")
+        );
+        assert_eq!(
+            "watchpoint 2: read 1 from 12 at pc 8\n0002: OUT [12]",
+            reply(&mut debugger, "c")
+        );
+        assert!(reply(&mut debugger, "w 5 1").starts_with("error"));
+    }
+
+    #[test]
+    fn repl_sets_conditional_breakpoints() {
+        let mut debugger = debugger(COUNTDOWN, vec![3]);
+        assert_eq!(
+            "breakpoint 1 set",
+            reply(&mut debugger, "when pc == 2 && mem[12] < 3")
+        );
+        assert_eq!("1: pc == 2 && mem[12] < 3", reply(&mut debugger, "when"));
+        assert_eq!("output 3", reply(&mut debugger, "c"));
+        assert_eq!(
+            "breakpoint 1 hit\n0002: OUT [12]",
+            reply(&mut debugger, "c")
+        );
+        assert_eq!("output 2", reply(&mut debugger, "c"));
+        assert!(reply(&mut debugger, "when pc ==").starts_with("error"));
+    }
+
+    #[test]
+    fn repl_saves_and_loads() {
+        let path = std::env::temp_dir().join(format!("debugger-{}.sav", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut debugger = debugger(COUNTDOWN, vec![2]);
+        assert_eq!("output 2", reply(&mut debugger, "c"));
+        assert_eq!(
+            format!("saved to {}", path),
+            reply(&mut debugger, &format!("save {}", path))
+        );
+        assert_eq!("output 1", reply(&mut debugger, "c"));
+        assert_eq!(
+            format!("loaded {}\n0004: ADD [12], #-1 -> [12]", path),
+            reply(&mut debugger, &format!("load {}", path))
+        );
+        std::fs::remove_file(path).unwrap();
+        assert_eq!("output 1", reply(&mut debugger, "c"));
+        assert!(reply(&mut debugger, &format!("load {}", path)).starts_with("error"));
+    }
+
+    #[test]
+    fn errors_are_reported_not_fatal() {
+        let mut debugger = debugger("db 42", vec![]);
+        assert!(debugger.step().is_err());
+        assert!(reply(&mut debugger, "s").starts_with("error: InvalidOpcode"));
+    }
+}
@@ -0,0 +1,195 @@
+use crate::intcode::*;
+
+// addresses past this are decoded every time rather than cached, since code this far out is rare
+pub const CACHED_ADDRESSES: usize = 1 << 16;
+
+// instructions to decode before caching any, since a short run (like one of day 7's
+// amplifiers, which take a few dozen) is over before filling the cache would pay off
+pub const WARM_UP: u64 = 1000;
+
+// an instruction word split into its opcode and parameter modes, so it only has to be done once
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Decoded {
+    pub word: i64,
+    pub opcode: i64,
+    // None where the mode digit isn't a mode, which is only a fault if that parameter is used
+    pub modes: [Option<Mode>; 3],
+}
+
+impl Decoded {
+    pub fn new(word: i64) -> Decoded {
+        Decoded {
+            word,
+            opcode: get_opcode(word),
+            modes: [1, 2, 3].map(|index| decode_parameter(word, index).ok()),
+        }
+    }
+
+    pub fn mode(&self, index: usize) -> Option<Mode> {
+        self.modes.get(index.checked_sub(1)?).copied().flatten()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodeCache {
+    entries: Vec<Option<Decoded>>,
+    warm_up: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl Default for DecodeCache {
+    fn default() -> DecodeCache {
+        DecodeCache::with_warm_up(WARM_UP)
+    }
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        DecodeCache::default()
+    }
+
+    pub fn with_warm_up(warm_up: u64) -> DecodeCache {
+        DecodeCache {
+            entries: vec![],
+            warm_up,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    // the word is checked against what was cached, so memory changed without the cache
+    // hearing about it (through Emulator::program, say) still decodes correctly
+    pub fn decode(&mut self, address: usize, word: i64) -> Decoded {
+        if let Some(Some(decoded)) = self.entries.get(address) {
+            if decoded.word == word {
+                self.hits += 1;
+                return *decoded;
+            }
+        }
+        self.misses += 1;
+        let decoded = Decoded::new(word);
+        if self.misses > self.warm_up && address < CACHED_ADDRESSES {
+            if address >= self.entries.len() {
+                self.entries.resize(address + 1, None);
+            }
+            self.entries[address] = Some(decoded);
+        }
+        decoded
+    }
+
+    // for self-modifying code, called whenever the emulator writes to memory
+    pub fn invalidate(&mut self, address: usize) {
+        if let Some(entry) = self.entries.get_mut(address) {
+            *entry = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // how many addresses have a decoded instruction cached
+    pub fn len(&self) -> usize {
+        self.entries.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+    use crate::decode::*;
+
+    #[test]
+    fn decodes_opcodes_and_modes() {
+        let decoded = Decoded::new(21002);
+        assert_eq!(2, decoded.opcode);
+        assert_eq!(
+            [
+                Some(Mode::Position),
+                Some(Mode::Immediate),
+                Some(Mode::Relative)
+            ],
+            decoded.modes
+        );
+        assert_eq!(Some(Mode::Relative), decoded.mode(3));
+        assert_eq!(None, decoded.mode(0));
+        assert_eq!(None, decoded.mode(4));
+        assert_eq!(None, Decoded::new(301).mode(1));
+    }
+
+    #[test]
+    fn caches_until_invalidated() {
+        let mut cache = DecodeCache::with_warm_up(0);
+        assert_eq!(1, cache.decode(4, 1101).opcode);
+        assert_eq!(1, cache.decode(4, 1101).opcode);
+        assert_eq!((1, 1), (cache.hits(), cache.misses()));
+
+        cache.invalidate(4);
+        assert!(cache.is_empty());
+        cache.decode(4, 1101);
+        // a different word at the same address is decoded afresh
+        assert_eq!(99, cache.decode(4, 99).opcode);
+        assert_eq!((1, 3), (cache.hits(), cache.misses()));
+
+        cache.decode(CACHED_ADDRESSES, 99);
+        assert_eq!(1, cache.len());
+    }
+
+    #[test]
+    fn nothing_is_cached_while_warming_up() {
+        let mut cache = DecodeCache::with_warm_up(2);
+        for _ in 0..4 {
+            cache.decode(0, 99);
+        }
+        assert_eq!((1, 3), (cache.hits(), cache.misses()));
+        assert_eq!(1, cache.len());
+    }
+
+    // count down from 2000, long enough for the cache to warm up, then hlt
+    const COUNTDOWN: &str = "
+        loop:   add [count], #-1 -> [count]
+                jt [count], #loop
+                add #0, #99 -> [loop]
+                jt #1, #loop
+        count:  db 2000
+    ";
+
+    #[test]
+    fn self_modifying_programs_run_the_new_instructions() {
+        let program = assemble(COUNTDOWN).unwrap();
+        for cached in &[true, false] {
+            let mut emulator = Emulator::new(program.clone(), vec![], false);
+            emulator.set_decode_cache(*cached);
+            assert_eq!(RunSignal::Halt, emulator.run_program().unwrap());
+            assert_eq!(4003, emulator.instructions_executed());
+        }
+        let mut emulator = Emulator::new(program.clone(), vec![], false);
+        assert!(emulator.decode_cache().is_none());
+        emulator.set_decode_cache(true);
+        emulator.run_program().unwrap();
+        assert!(emulator.decode_cache().unwrap().hits() > 2000);
+
+        // and when the loop is turned into a hlt behind the emulator's back
+        let mut emulator = Emulator::new(program, vec![], false);
+        emulator.set_decode_cache(true);
+        emulator.set_instruction_budget(Some(3000));
+        assert_eq!(RunSignal::BudgetExhausted, emulator.run_program().unwrap());
+        emulator.program[0] = 99;
+        emulator.set_instruction_budget(None);
+        assert_eq!(RunSignal::Halt, emulator.run_program().unwrap());
+        assert_eq!(3001, emulator.instructions_executed());
+    }
+}
@@ -0,0 +1,241 @@
+use crate::intcode::*;
+use crate::opcodes::OpcodeTable;
+use std::fmt;
+
+// the opcode plus its parameters, for the widest instruction there is
+pub const MAX_INSTRUCTION_LENGTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Operand {
+    Position(i64),
+    Immediate(i64),
+    Relative(i64),
+}
+
+impl Operand {
+    fn decode(instruction: i64, index: usize, value: i64) -> Option<Operand> {
+        match decode_parameter(instruction, index).ok()? {
+            Mode::Position => Some(Operand::Position(value)),
+            Mode::Immediate => Some(Operand::Immediate(value)),
+            Mode::Relative => Some(Operand::Relative(value)),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Position(address) => write!(f, "[{}]", address),
+            Operand::Immediate(value) => write!(f, "#{}", value),
+            Operand::Relative(offset) if *offset < 0 => write!(f, "[r-{}]", offset.unsigned_abs()),
+            Operand::Relative(offset) => write!(f, "[r+{}]", offset),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Op {
+    Code {
+        info: OpcodeInfo,
+        operands: Vec<Operand>,
+    },
+    // a word that can't be decoded as an instruction, most likely data mixed into the code
+    Data(i64),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Instruction {
+    pub address: usize,
+    pub words: Vec<i64>,
+    pub op: Op,
+}
+
+impl Instruction {
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}: ", self.address)?;
+        let (info, operands) = match &self.op {
+            Op::Data(value) => return write!(f, "DATA {}", value),
+            Op::Code { info, operands } => (info, operands),
+        };
+        write!(f, "{}", info.mnemonic)?;
+        // reads are listed first, and the written parameter (if any) goes after an arrow
+        let reads: Vec<String> = operands
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| info.writes != Some(i + 1))
+            .map(|(_, operand)| operand.to_string())
+            .collect();
+        if !reads.is_empty() {
+            write!(f, " {}", reads.join(", "))?;
+        }
+        if let Some(index) = info.writes {
+            write!(f, " -> {}", operands[index - 1])?;
+        }
+        Ok(())
+    }
+}
+
+// decode the single instruction at address, or None if those words don't make a valid instruction
+pub fn decode_instruction(program: &[i64], address: usize) -> Option<Instruction> {
+    decode_instruction_with(program, address, &OpcodeTable::new())
+}
+
+// the same, knowing about whatever custom opcodes are in the table as well
+pub fn decode_instruction_with(
+    program: &[i64],
+    address: usize,
+    opcodes: &OpcodeTable,
+) -> Option<Instruction> {
+    let instruction = *program.get(address)?;
+    if instruction < 0 {
+        return None;
+    }
+    let info = opcodes.info(get_opcode(instruction))?;
+    // leftover mode digits beyond the parameters the opcode takes mean this probably isn't code
+    if instruction / 10_i64.pow(2 + info.parameters as u32) != 0 {
+        return None;
+    }
+    let words = program.get(address..address + 1 + info.parameters)?;
+    let mut operands = vec![];
+    for (index, value) in words.iter().enumerate().skip(1) {
+        let operand = Operand::decode(instruction, index, *value)?;
+        if info.writes == Some(index) {
+            if let Operand::Immediate(_) = operand {
+                return None;
+            }
+        }
+        operands.push(operand);
+    }
+    Some(Instruction {
+        address,
+        words: words.to_vec(),
+        op: Op::Code { info, operands },
+    })
+}
+
+pub fn disassemble(program: &[i64]) -> Vec<Instruction> {
+    disassemble_with(program, &OpcodeTable::new())
+}
+
+pub fn disassemble_with(program: &[i64], opcodes: &OpcodeTable) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut address = 0;
+    while address < program.len() {
+        let instruction = match decode_instruction_with(program, address, opcodes) {
+            Some(instruction) => instruction,
+            None => Instruction {
+                address,
+                words: vec![program[address]],
+                op: Op::Data(program[address]),
+            },
+        };
+        address += instruction.len();
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+pub fn render(instructions: &[Instruction]) -> String {
+    let lines: Vec<String> = instructions.iter().map(|i| i.to_string()).collect();
+    lines.join("\n")
+}
+
+pub fn disassemble_to_string(program: &[i64]) -> String {
+    render(&disassemble(program))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disassembler::*;
+
+    #[test]
+    fn operands_render() {
+        assert_eq!("[100]", Operand::Position(100).to_string());
+        assert_eq!("#-5", Operand::Immediate(-5).to_string());
+        assert_eq!("[r+3]", Operand::Relative(3).to_string());
+        assert_eq!("[r-1]", Operand::Relative(-1).to_string());
+    }
+
+    #[test]
+    fn disassemble_works() {
+        let program = common::comma_separated_i64_to_vec(
+            &"109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99".to_string(),
+        );
+        assert_eq!(
+            "0000: ARB #1\n\
+             0002: OUT [r-1]\n\
+             0004: ADD [100], #1 -> [100]\n\
+             0008: EQ [100], #16 -> [101]\n\
+             0012: JF [101], #0\n\
+             0015: HLT",
+            disassemble_to_string(&program)
+        );
+    }
+
+    #[test]
+    fn disassemble_renders_relative_writes() {
+        let program = vec![22201, 3, 5, 100, 203, 7];
+        assert_eq!(
+            "0000: ADD [r+3], [r+5] -> [r+100]\n0004: IN -> [r+7]",
+            disassemble_to_string(&program)
+        );
+    }
+
+    #[test]
+    fn disassemble_marks_data() {
+        // 42 isn't an opcode, 11101 writes to an immediate, 10099 has a stray mode digit
+        // and the trailing ADD runs off the end of the program
+        let program = vec![42, 11101, 0, 0, 0, 10099, -7, 1, 0, 0];
+        let instructions = disassemble(&program);
+        assert_eq!(
+            vec![
+                Op::Data(42),
+                Op::Data(11101),
+                Op::Data(0),
+                Op::Data(0),
+                Op::Data(0),
+                Op::Data(10099),
+                Op::Data(-7),
+                Op::Data(1),
+                Op::Data(0),
+                Op::Data(0),
+            ],
+            instructions.into_iter().map(|i| i.op).collect::<Vec<Op>>()
+        );
+        assert_eq!("0006: DATA -7", disassemble(&program)[6].to_string());
+    }
+
+    #[test]
+    fn disassemble_reports_structure() {
+        let instructions = disassemble(&[1002, 4, 3, 4, 33, 99]);
+        assert_eq!(3, instructions.len());
+        assert_eq!(0, instructions[0].address);
+        assert_eq!(vec![1002, 4, 3, 4], instructions[0].words);
+        match &instructions[0].op {
+            Op::Code { info, operands } => {
+                assert_eq!(2, info.opcode);
+                assert_eq!(
+                    vec![
+                        Operand::Position(4),
+                        Operand::Immediate(3),
+                        Operand::Position(4)
+                    ],
+                    *operands
+                );
+            }
+            op => panic!("unexpected {:?}", op),
+        }
+        assert_eq!(Op::Data(33), instructions[1].op);
+        assert_eq!(5, instructions[2].address);
+    }
+}
@@ -0,0 +1,443 @@
+use crate::coverage::Coverage;
+use crate::disassembler::*;
+use crate::opcodes::OpcodeTable;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+/*
+    A control-flow graph of a program, worked out from the code without running it.
+    Instructions are followed from address 0, on to the next one and along every jump
+    with an immediate target, so data that's never reached isn't mistaken for code.
+    Blocks start wherever a jump lands or after one that might not be taken, and end
+    at a jump or a halt.
+
+    A jump to a position or relative parameter, like a subroutine returning to the
+    address it was called from, could go anywhere, so the block is left unresolved.
+    Given the coverage of a run, everywhere those jumps went is filled in as observed
+    edges and followed like any other, and so is everywhere a custom opcode was seen to
+    jump. Programs that rewrite their own code, or custom opcodes that jump in ways no
+    run has seen, can still go places the graph doesn't know about.
+
+        b0 [label="0000: JT [7], #6\l"];
+        b3 [label="0003: JT [7], [8]\l"];
+        u3 [shape=plaintext, label="?"];
+        b3 -> u3 [style=dashed];
+        b6 [label="0006: HLT\l"];
+        b0 -> b3;
+        b0 -> b6 [label="jump"];
+        b3 -> b6;
+*/
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum EdgeKind {
+    // on to the next instruction, including past a jump that isn't taken
+    FallThrough,
+    // a jump to an immediate target
+    Jump,
+    // a jump without one, seen going here in a run
+    Observed,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Edge {
+    // the start addresses of the blocks at each end
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Block {
+    pub instructions: Vec<Instruction>,
+    // ends in a jump that could go anywhere, and no run has been seen to take it anywhere
+    pub unresolved: bool,
+}
+
+impl Block {
+    pub fn start(&self) -> usize {
+        self.instructions[0].address
+    }
+
+    // one past its last word
+    pub fn end(&self) -> usize {
+        let last = &self.instructions[self.instructions.len() - 1];
+        last.address + last.len()
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct FlowGraph {
+    blocks: BTreeMap<usize, Block>,
+    edges: BTreeSet<Edge>,
+}
+
+// where control can go after a single instruction
+#[derive(Debug, Clone, Default)]
+struct Flow {
+    targets: Vec<(usize, EdgeKind)>,
+    falls_through: bool,
+    // jumps end a block even when they might fall through
+    ends_block: bool,
+    unresolved: bool,
+}
+
+fn flow(instruction: &Instruction, coverage: Option<&Coverage>) -> Flow {
+    let (info, operands) = match &instruction.op {
+        Op::Code { info, operands } => (info, operands),
+        Op::Data(_) => return Flow::default(),
+    };
+    match info.opcode {
+        5 | 6 => {
+            // an immediate condition means the jump is always or never taken
+            let taken = match operands[0] {
+                Operand::Immediate(value) => Some((value != 0) == (info.opcode == 5)),
+                _ => None,
+            };
+            let mut flow = Flow {
+                falls_through: taken != Some(true),
+                ends_block: true,
+                ..Flow::default()
+            };
+            if taken == Some(false) {
+                return flow;
+            }
+            match operands[1] {
+                // a negative target is an error, not somewhere to go
+                Operand::Immediate(target) => flow.targets.extend(
+                    usize::try_from(target)
+                        .ok()
+                        .map(|target| (target, EdgeKind::Jump)),
+                ),
+                _ => {
+                    let next = instruction.address + instruction.len();
+                    let seen = coverage.map_or(vec![], |coverage| {
+                        coverage
+                            .jump_targets(instruction.address)
+                            .filter(|to| !(flow.falls_through && *to == next))
+                            .map(|to| (to, EdgeKind::Observed))
+                            .collect()
+                    });
+                    flow.unresolved = seen.is_empty();
+                    flow.targets = seen;
+                }
+            }
+            flow
+        }
+        99 => Flow {
+            ends_block: true,
+            ..Flow::default()
+        },
+        // custom opcodes jump wherever their handlers say, so only a run can tell where
+        _ => {
+            let seen: Vec<(usize, EdgeKind)> = coverage.map_or(vec![], |coverage| {
+                coverage
+                    .jump_targets(instruction.address)
+                    .map(|to| (to, EdgeKind::Observed))
+                    .collect()
+            });
+            Flow {
+                falls_through: true,
+                ends_block: !seen.is_empty(),
+                targets: seen,
+                ..Flow::default()
+            }
+        }
+    }
+}
+
+// quotes and backslashes are all that need escaping in a DOT string
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl FlowGraph {
+    pub fn new(program: &[i64]) -> FlowGraph {
+        FlowGraph::build(program, &OpcodeTable::new(), None)
+    }
+
+    // the same, decoding any custom opcodes and filling in the jumps coverage saw
+    pub fn build(program: &[i64], opcodes: &OpcodeTable, coverage: Option<&Coverage>) -> FlowGraph {
+        // first everything that can be reached, and where blocks have to start
+        let mut reached: BTreeMap<usize, (Instruction, Flow)> = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        let mut pending = vec![0];
+        while let Some(address) = pending.pop() {
+            if reached.contains_key(&address) {
+                continue;
+            }
+            let instruction = match decode_instruction_with(program, address, opcodes) {
+                Some(instruction) => instruction,
+                // ran into data, or off the end
+                None => continue,
+            };
+            let flow = flow(&instruction, coverage);
+            for (target, _) in &flow.targets {
+                leaders.insert(*target);
+                pending.push(*target);
+            }
+            if flow.falls_through {
+                let next = address + instruction.len();
+                if flow.ends_block {
+                    leaders.insert(next);
+                }
+                pending.push(next);
+            }
+            reached.insert(address, (instruction, flow));
+        }
+
+        // then each block runs on from where it starts until it jumps, halts or meets another.
+        // A jump into the middle of an instruction makes blocks that overlap, which is fine
+        let mut graph = FlowGraph::default();
+        for start in leaders.iter().filter(|start| reached.contains_key(start)) {
+            let mut block = Block {
+                instructions: vec![],
+                unresolved: false,
+            };
+            let mut address = *start;
+            while let Some((instruction, flow)) = reached.get(&address) {
+                block.instructions.push(instruction.clone());
+                block.unresolved = flow.unresolved;
+                address += instruction.len();
+                for (to, kind) in &flow.targets {
+                    graph.edges.insert(Edge {
+                        from: *start,
+                        to: *to,
+                        kind: *kind,
+                    });
+                }
+                if !flow.falls_through {
+                    break;
+                }
+                if flow.ends_block || leaders.contains(&address) {
+                    graph.edges.insert(Edge {
+                        from: *start,
+                        to: address,
+                        kind: EdgeKind::FallThrough,
+                    });
+                    break;
+                }
+            }
+            graph.blocks.insert(*start, block);
+        }
+        // edges into words that don't decode don't lead to a block
+        let blocks = &graph.blocks;
+        graph.edges.retain(|edge| blocks.contains_key(&edge.to));
+        graph
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    pub fn block(&self, start: usize) -> Option<&Block> {
+        self.blocks.get(&start)
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &Edge> {
+        self.edges.iter()
+    }
+
+    pub fn successors(&self, start: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.from == start)
+    }
+
+    pub fn unresolved(&self) -> impl Iterator<Item = &Block> {
+        self.blocks().filter(|block| block.unresolved)
+    }
+
+    // for Graphviz, e.g. 'dot -Tsvg program.dot -o program.svg'
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph program {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for (start, block) in &self.blocks {
+            let lines: Vec<String> = block
+                .instructions
+                .iter()
+                .map(|instruction| format!("{}\\l", escape(&instruction.to_string())))
+                .collect();
+            writeln!(dot, "    b{} [label=\"{}\"];", start, lines.concat()).unwrap();
+            if block.unresolved {
+                writeln!(dot, "    u{} [shape=plaintext, label=\"?\"];", start).unwrap();
+                writeln!(dot, "    b{} -> u{} [style=dashed];", start, start).unwrap();
+            }
+        }
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::FallThrough => "",
+                EdgeKind::Jump => " [label=\"jump\"]",
+                EdgeKind::Observed => " [label=\"seen\", style=dashed]",
+            };
+            writeln!(dot, "    b{} -> b{}{};", edge.from, edge.to, style).unwrap();
+        }
+        dot.push('}');
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+    use crate::flowgraph::*;
+    use crate::intcode::*;
+    use crate::opcodes::*;
+
+    // doubles its input twice over with a subroutine, which returns through the stack
+    const SUBROUTINE: &str = "
+                arb #stack
+                in -> [value]
+                call #double
+                out [value]
+                call #double
+                out [value]
+                hlt
+        double: mul [value], #2 -> [value]
+                ret
+        value:  db 0
+        stack:  db 0
+    ";
+
+    fn starts(graph: &FlowGraph) -> Vec<usize> {
+        graph.blocks().map(|block| block.start()).collect()
+    }
+
+    fn edges(graph: &FlowGraph) -> Vec<(usize, usize, EdgeKind)> {
+        graph
+            .edges()
+            .map(|edge| (edge.from, edge.to, edge.kind))
+            .collect()
+    }
+
+    #[test]
+    fn splits_programs_into_blocks() {
+        // jt [7], #6; jt [7], [8]; hlt; data
+        let graph = FlowGraph::new(&[1005, 7, 6, 5, 7, 8, 99, 0, 0]);
+        assert_eq!(vec![0, 3, 6], starts(&graph));
+        assert_eq!(
+            vec![
+                (0, 3, EdgeKind::FallThrough),
+                (0, 6, EdgeKind::Jump),
+                (3, 6, EdgeKind::FallThrough),
+            ],
+            edges(&graph)
+        );
+        assert_eq!(
+            vec![3],
+            graph.unresolved().map(Block::start).collect::<Vec<usize>>()
+        );
+        assert_eq!(
+            "\
+digraph program {
+    node [shape=box, fontname=\"monospace\"];
+    b0 [label=\"0000: JT [7], #6\\l\"];
+    b3 [label=\"0003: JT [7], [8]\\l\"];
+    u3 [shape=plaintext, label=\"?\"];
+    b3 -> u3 [style=dashed];
+    b6 [label=\"0006: HLT\\l\"];
+    b0 -> b3;
+    b0 -> b6 [label=\"jump\"];
+    b3 -> b6;
+}",
+            graph.to_dot()
+        );
+    }
+
+    #[test]
+    fn follows_jumps_that_always_or_never_happen() {
+        // jt #1, #5; data; jf #1, #99; add [0], [0] -> [0]; hlt
+        let program = vec![1105, 1, 5, -1, -1, 1106, 1, 99, 1, 0, 0, 0, 99];
+        let graph = FlowGraph::new(&program);
+        assert_eq!(vec![0, 5, 8], starts(&graph));
+        assert_eq!(
+            vec![(0, 5, EdgeKind::Jump), (5, 8, EdgeKind::FallThrough)],
+            edges(&graph)
+        );
+        let block = graph.block(8).unwrap();
+        assert_eq!((2, 13), (block.instructions.len(), block.end()));
+        assert_eq!(0, graph.unresolved().count());
+    }
+
+    #[test]
+    fn coverage_fills_in_indirect_jumps() {
+        let program = assemble(SUBROUTINE).unwrap();
+        // all that's known without running it is the first call
+        let graph = FlowGraph::new(&program);
+        assert_eq!(vec![0, 27], starts(&graph));
+        assert_eq!(vec![(0, 27, EdgeKind::Jump)], edges(&graph));
+        assert!(graph.block(27).unwrap().unresolved);
+
+        let mut emulator = Emulator::new(program.clone(), vec![5], false);
+        emulator.set_coverage(true);
+        let mut outputs = vec![];
+        emulator
+            .run_with(&mut crate::io::from_iter(vec![]), &mut outputs)
+            .unwrap();
+        assert_eq!(vec![10, 20], outputs);
+
+        let graph = FlowGraph::build(&program, emulator.opcodes(), emulator.coverage());
+        assert_eq!(vec![0, 13, 24, 27], starts(&graph));
+        assert_eq!(
+            vec![
+                (0, 27, EdgeKind::Jump),
+                (13, 27, EdgeKind::Jump),
+                (27, 13, EdgeKind::Observed),
+                (27, 24, EdgeKind::Observed),
+            ],
+            edges(&graph)
+        );
+        assert_eq!(0, graph.unresolved().count());
+        assert_eq!(
+            vec![(27, 13, EdgeKind::Observed), (27, 24, EdgeKind::Observed)],
+            graph
+                .successors(27)
+                .map(|edge| (edge.from, edge.to, edge.kind))
+                .collect::<Vec<_>>()
+        );
+        assert!(graph
+            .to_dot()
+            .contains("b27 -> b13 [label=\"seen\", style=dashed];"));
+    }
+
+    #[test]
+    fn coverage_fills_in_custom_jumps() {
+        // nop; goto #0, where goto is a custom opcode, then a hlt it never reaches
+        let program = vec![20, 121, 0, 99];
+        let mut emulator = Emulator::new(program.clone(), vec![], false);
+        let nop = OpcodeInfo {
+            opcode: 20,
+            mnemonic: "NOP",
+            parameters: 0,
+            writes: None,
+        };
+        emulator
+            .add_opcode(nop, |_: &mut Operation| Ok(()))
+            .unwrap();
+        let goto = OpcodeInfo {
+            opcode: 21,
+            mnemonic: "GOTO",
+            parameters: 1,
+            writes: None,
+        };
+        emulator
+            .add_opcode(goto, |operation: &mut Operation| {
+                operation.jump = Some(operation.operands[0] as usize);
+                Ok(())
+            })
+            .unwrap();
+        let graph = FlowGraph::build(&program, emulator.opcodes(), None);
+        assert_eq!(vec![0], starts(&graph));
+
+        emulator.set_coverage(true);
+        emulator.set_instruction_budget(Some(4));
+        emulator.run_program().unwrap();
+        let graph = FlowGraph::build(&program, emulator.opcodes(), emulator.coverage());
+        assert_eq!(vec![0, 3], starts(&graph));
+        assert_eq!(
+            vec![(0, 0, EdgeKind::Observed), (0, 3, EdgeKind::FallThrough)],
+            edges(&graph)
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use crate::word::Word;
+use std::collections::VecDeque;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::SyncSender;
+
+/*
+    Where an emulator's inputs come from and its outputs go, for Emulator::run_with.
+    A source returning None means nothing more is coming for now, and the run stops
+    with RunSignal::NoInput just as it does when the inputs queue is empty.
+*/
+
+pub trait InputSource<W = i64> {
+    fn next_input(&mut self) -> Option<W>;
+}
+
+pub trait OutputSink<W = i64> {
+    fn write_output(&mut self, value: W);
+}
+
+impl<W, T: InputSource<W> + ?Sized> InputSource<W> for &mut T {
+    fn next_input(&mut self) -> Option<W> {
+        (**self).next_input()
+    }
+}
+
+impl<W, T: InputSource<W> + ?Sized> InputSource<W> for Box<T> {
+    fn next_input(&mut self) -> Option<W> {
+        (**self).next_input()
+    }
+}
+
+impl<W, T: OutputSink<W> + ?Sized> OutputSink<W> for &mut T {
+    fn write_output(&mut self, value: W) {
+        (**self).write_output(value)
+    }
+}
+
+impl<W, T: OutputSink<W> + ?Sized> OutputSink<W> for Box<T> {
+    fn write_output(&mut self, value: W) {
+        (**self).write_output(value)
+    }
+}
+
+impl<W> InputSource<W> for VecDeque<W> {
+    fn next_input(&mut self) -> Option<W> {
+        self.pop_front()
+    }
+}
+
+impl<W> OutputSink<W> for VecDeque<W> {
+    fn write_output(&mut self, value: W) {
+        self.push_back(value)
+    }
+}
+
+impl<W> OutputSink<W> for Vec<W> {
+    fn write_output(&mut self, value: W) {
+        self.push(value)
+    }
+}
+
+// blocks until a value arrives, and runs dry once every sender has gone
+impl<W> InputSource<W> for Receiver<W> {
+    fn next_input(&mut self) -> Option<W> {
+        self.recv().ok()
+    }
+}
+
+// anything sent after the receiver has gone is dropped, there's no one left to read it
+impl<W> OutputSink<W> for Sender<W> {
+    fn write_output(&mut self, value: W) {
+        let _ = self.send(value);
+    }
+}
+
+impl<W> OutputSink<W> for SyncSender<W> {
+    fn write_output(&mut self, value: W) {
+        let _ = self.send(value);
+    }
+}
+
+pub struct IterSource<I>(pub I);
+
+impl<W, I: Iterator<Item = W>> InputSource<W> for IterSource<I> {
+    fn next_input(&mut self) -> Option<W> {
+        self.0.next()
+    }
+}
+
+pub struct FnSource<F>(pub F);
+
+impl<W, F: FnMut() -> Option<W>> InputSource<W> for FnSource<F> {
+    fn next_input(&mut self) -> Option<W> {
+        (self.0)()
+    }
+}
+
+pub struct FnSink<F>(pub F);
+
+impl<W, F: FnMut(W)> OutputSink<W> for FnSink<F> {
+    fn write_output(&mut self, value: W) {
+        (self.0)(value)
+    }
+}
+
+pub fn from_iter<W, I: IntoIterator<Item = W>>(values: I) -> IterSource<I::IntoIter> {
+    IterSource(values.into_iter())
+}
+
+pub fn from_fn<W, F: FnMut() -> Option<W>>(f: F) -> FnSource<F> {
+    FnSource(f)
+}
+
+pub fn to_fn<W, F: FnMut(W)>(f: F) -> FnSink<F> {
+    FnSink(f)
+}
+
+// one number per line, running dry at the end of the input or on a line that isn't a number
+pub struct LineSource<R> {
+    reader: R,
+}
+
+impl<R: BufRead> LineSource<R> {
+    pub fn new(reader: R) -> LineSource<R> {
+        LineSource { reader }
+    }
+}
+
+impl LineSource<io::StdinLock<'static>> {
+    pub fn stdin() -> LineSource<io::StdinLock<'static>> {
+        LineSource::new(io::stdin().lock())
+    }
+}
+
+impl<W: Word, R: BufRead> InputSource<W> for LineSource<R> {
+    fn next_input(&mut self) -> Option<W> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            if !line.trim().is_empty() {
+                return line.trim().parse().ok();
+            }
+        }
+    }
+}
+
+// one number per line
+pub struct WriterSink<W> {
+    writer: W,
+    // the first write that failed, after which everything else is dropped
+    pub error: Option<io::Error>,
+}
+
+impl<W: Write> WriterSink<W> {
+    pub fn new(writer: W) -> WriterSink<W> {
+        WriterSink {
+            writer,
+            error: None,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl WriterSink<io::Stdout> {
+    pub fn stdout() -> WriterSink<io::Stdout> {
+        WriterSink::new(io::stdout())
+    }
+}
+
+impl<V: Word, W: Write> OutputSink<V> for WriterSink<W> {
+    fn write_output(&mut self, value: V) {
+        if self.error.is_none() {
+            if let Err(error) = writeln!(self.writer, "{}", value) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::io::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn sources_produce_values() {
+        let mut queue: VecDeque<i64> = vec![1, 2].into();
+        assert_eq!(Some(1), queue.next_input());
+
+        let mut iter = from_iter(vec![3, 4]);
+        assert_eq!(Some(3), iter.next_input());
+        assert_eq!(Some(4), iter.next_input());
+        assert_eq!(None, iter.next_input());
+
+        let mut count = 0;
+        let mut counter = from_fn(|| {
+            count += 1;
+            Some(count)
+        });
+        assert_eq!(Some(1), counter.next_input());
+        assert_eq!(Some(2), counter.next_input());
+
+        let mut lines = LineSource::new("5\n\n-6\nseven\n8\n".as_bytes());
+        assert_eq!(Some(5), lines.next_input());
+        assert_eq!(Some(-6), lines.next_input());
+        assert_eq!(None::<i64>, lines.next_input());
+
+        let (sender, mut receiver) = channel();
+        sender.send(9).unwrap();
+        drop(sender);
+        assert_eq!(Some(9), receiver.next_input());
+        assert_eq!(None, receiver.next_input());
+    }
+
+    #[test]
+    fn sinks_take_values() {
+        let mut values = vec![];
+        values.write_output(1);
+        assert_eq!(vec![1], values);
+
+        let mut total = 0;
+        to_fn(|value| total += value).write_output(5);
+        assert_eq!(5, total);
+
+        let mut writer = WriterSink::new(vec![]);
+        writer.write_output(12);
+        writer.write_output(-3);
+        assert_eq!("12\n-3\n", String::from_utf8(writer.into_inner()).unwrap());
+
+        let (mut sender, receiver) = channel();
+        sender.write_output(7);
+        assert_eq!(Ok(7), receiver.recv());
+        drop(receiver);
+        sender.write_output(8);
+    }
+}
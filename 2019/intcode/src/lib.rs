@@ -1,4 +1,54 @@
+pub mod ascii;
+pub mod assembler;
+pub mod condition;
+pub mod coverage;
+pub mod dataflow;
+pub mod debugger;
+pub mod decode;
+pub mod disassembler;
+pub mod flowgraph;
+pub mod io;
+pub mod loops;
+pub mod memory;
+pub mod network;
+pub mod opcodes;
+pub mod profiler;
+pub mod replay;
+pub mod savefile;
+pub mod threaded;
+pub mod trace;
+pub mod undo;
+pub mod word;
+
 pub mod intcode {
+    use crate::condition::Condition;
+    use crate::coverage::Coverage;
+    use crate::decode::DecodeCache;
+    use crate::decode::Decoded;
+    pub use crate::io::InputSource;
+    pub use crate::io::OutputSink;
+    use crate::loops::LoopDetector;
+    pub use crate::memory::Memory;
+    use crate::opcodes::Handler;
+    use crate::opcodes::OpcodeError;
+    use crate::opcodes::OpcodeTable;
+    pub use crate::opcodes::Operation;
+    use crate::profiler::Profile;
+    use crate::replay::Event;
+    use crate::replay::Recording;
+    use crate::trace::MemoryWrite;
+    use crate::trace::TraceRecord;
+    use crate::trace::Tracer;
+    use crate::undo::UndoEntry;
+    use crate::undo::UndoLog;
+    pub use crate::word::Arithmetic;
+    pub use crate::word::Word;
+    use std::collections::BTreeMap;
+    use std::collections::VecDeque;
+    use std::fmt;
+    use std::ops::RangeInclusive;
+    use std::sync::Arc;
+
     pub fn prepare_emulator(program_spec: String, input_spec: String, debug: bool) -> Emulator {
         Emulator::new(
             common::comma_separated_i64_to_vec(&program_spec),
@@ -9,7 +59,7 @@ pub mod intcode {
 
     pub fn deconstruct_output(emulator: Emulator) -> (String, String) {
         (
-            common::vec_to_comma_separated_i64(emulator.program),
+            common::vec_to_comma_separated_i64(emulator.program.to_vec(emulator.image())),
             common::vec_to_comma_separated_i64(emulator.outputs),
         )
     }
@@ -27,37 +77,74 @@ pub mod intcode {
         instruction % 100
     }
 
-    pub fn decode_parameter(instruction: i64, index: usize) -> Mode {
-        match index {
-            1 => decode_mode((instruction / 100) % 10),
-            2 => decode_mode((instruction / 1000) % 10),
-            //unlikely this'll get hit, since
-            //currently all the 3-parameter opcodes write to the 3rd parameter
-            3 => decode_mode((instruction / 10000) % 10),
-            _ => panic!("UNEXPECTED PARAMETER '{}'", index),
-        }
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub struct OpcodeInfo {
+        pub opcode: i64,
+        pub mnemonic: &'static str,
+        pub parameters: usize,
+        // the parameter the result is written to, if the opcode writes one
+        pub writes: Option<usize>,
     }
 
-    pub fn decode_mode(mode: i64) -> Mode {
+    pub fn opcode_info(opcode: i64) -> Option<OpcodeInfo> {
+        let (mnemonic, parameters, writes) = match opcode {
+            1 => ("ADD", 3, Some(3)),
+            2 => ("MUL", 3, Some(3)),
+            3 => ("IN", 1, Some(1)),
+            4 => ("OUT", 1, None),
+            5 => ("JT", 2, None),
+            6 => ("JF", 2, None),
+            7 => ("LT", 3, Some(3)),
+            8 => ("EQ", 3, Some(3)),
+            9 => ("ARB", 1, None),
+            99 => ("HLT", 0, None),
+            _ => return None,
+        };
+        Some(OpcodeInfo {
+            opcode,
+            mnemonic,
+            parameters,
+            writes,
+        })
+    }
+
+    pub fn decode_parameter(instruction: i64, index: usize) -> Result<Mode, ErrorKind> {
+        decode_mode(mode_digit(instruction, index))
+    }
+
+    pub fn decode_mode(mode: i64) -> Result<Mode, ErrorKind> {
         match mode {
-            0 => Mode::Position,
-            1 => Mode::Immediate,
-            2 => Mode::Relative,
-            _ => panic!("INVALID MODE {}", mode),
+            0 => Ok(Mode::Position),
+            1 => Ok(Mode::Immediate),
+            2 => Ok(Mode::Relative),
+            _ => Err(ErrorKind::InvalidMode),
+        }
+    }
+
+    // the raw mode digit for a parameter, before it's been checked
+    pub fn mode_digit(instruction: i64, index: usize) -> i64 {
+        match index {
+            1 => (instruction / 100) % 10,
+            2 => (instruction / 1000) % 10,
+            //unlikely this'll get hit, since
+            //currently all the 3-parameter opcodes write to the 3rd parameter
+            3 => (instruction / 10000) % 10,
+            // no opcode takes more than 3 parameters, so report it as a mode we can't decode
+            _ => -1,
         }
     }
 
-    pub fn add_i64_to_usize(a: i64, b: usize) -> usize {
+    pub fn add_i64_to_usize(a: i64, b: usize) -> Option<usize> {
         if a == 0 {
-            return b;
+            return Some(b);
         }
         match a.is_negative() {
-            true => b.checked_sub(a.abs() as usize).unwrap(),
-            false => b.checked_add(a as usize).unwrap(),
+            true => b.checked_sub(a.unsigned_abs() as usize),
+            false => b.checked_add(a as usize),
         }
     }
 
-    #[derive(Debug, Eq, PartialEq)]
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
     pub enum Mode {
         // 0
         Position,
@@ -67,248 +154,1125 @@ pub mod intcode {
         Relative,
     }
 
-    pub struct Emulator {
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub enum ErrorKind {
+        InvalidOpcode,
+        InvalidMode,
+        WriteToImmediate,
+        NegativeAddress,
+        OutOfBounds,
+        InfiniteLoop,
+        // an add or mul whose result didn't fit, with checked arithmetic
+        Overflow,
+        // a custom opcode's handler returned an error
+        HandlerFailed,
+        // in strict mode, a mode digit for a parameter the opcode doesn't have
+        StrayMode,
+        // in strict mode, a read past the end of the program that was loaded
+        OutsideImage,
+    }
+
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub struct IntcodeError {
+        pub kind: ErrorKind,
+        pub pc: usize,
+        pub instruction: i64,
+        // which parameter of the instruction was at fault, if any, and its raw mode digit
+        pub parameter: Option<usize>,
+        pub mode: Option<i64>,
+        // for an infinite loop, the lowest and highest pcs it goes round
+        pub loop_pcs: Option<RangeInclusive<usize>>,
+        // these two are boxed to keep errors small, since they're passed around a lot.
+        // For an overflow, the words that were being added or multiplied
+        pub operands: Box<[String]>,
+        // what a failed handler had to say, or what strict mode objected to
+        pub message: Option<Box<str>>,
+    }
+
+    impl fmt::Display for IntcodeError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "{:?} at pc {} (instruction {})",
+                self.kind, self.pc, self.instruction
+            )?;
+            if let Some(parameter) = self.parameter {
+                write!(f, ", parameter {}", parameter)?;
+            }
+            if let Some(mode) = self.mode {
+                write!(f, ", mode {}", mode)?;
+            }
+            if let Some(pcs) = &self.loop_pcs {
+                write!(f, ", looping over pcs {}..={}", pcs.start(), pcs.end())?;
+            }
+            if !self.operands.is_empty() {
+                write!(f, ", operands {}", self.operands.join(", "))?;
+            }
+            if let Some(message) = &self.message {
+                write!(f, ", {}", message)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl std::error::Error for IntcodeError {}
+
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub enum Access {
+        Read,
+        Write,
+        ReadWrite,
+    }
+
+    impl Access {
+        fn covers(self, access: Access) -> bool {
+            self == Access::ReadWrite || self == access
+        }
+    }
+
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub struct Watchpoint {
+        pub id: usize,
+        pub addresses: RangeInclusive<usize>,
+        pub access: Access,
+    }
+
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub struct WatchHit<W = i64> {
+        pub watchpoint: usize,
+        // the instruction that made the access
+        pub pc: usize,
+        pub address: usize,
+        pub access: Access,
+        // the value read, or the value written
+        pub value: W,
+    }
+
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub struct Breakpoint {
+        pub id: usize,
+        pub condition: Condition,
+    }
+
+    // the machine state, without any watchpoints or breakpoints set on it
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub struct Snapshot<W: Word = i64> {
+        pub memory: Memory<W>,
+        pub pc: usize,
+        pub relative_base: usize,
+        pub inputs: Vec<W>,
+        pub outputs: Vec<W>,
+        pub is_halted: bool,
+        // how many words the program was loaded with, however far it has written since
+        pub image: usize,
+    }
+
+    // cloning is cheap, the memory pages are shared until one of the clones writes to them.
+    // Words are i64 unless a program needs something narrower or wider, see crate::word
+    #[derive(Clone)]
+    pub struct Emulator<W: Word = i64> {
         pc: usize,
         relative_base: usize,
-        debug: bool,
-        pub program: Vec<i64>,
-        pub inputs: Vec<i64>,
-        pub outputs: Vec<i64>,
+        pub program: Memory<W>,
+        pub inputs: Vec<W>,
+        pub outputs: Vec<W>,
         is_halted: bool,
+        watchpoints: Vec<Watchpoint>,
+        breakpoints: Vec<Breakpoint>,
+        next_id: usize,
+        // accesses that fired a watchpoint and haven't been reported yet
+        watch_hits: VecDeque<WatchHit<W>>,
+        // a signal held back while watch hits from the same instruction are reported
+        pending_signal: Option<RunSignal<W>>,
+        // set when a breakpoint fires, so resuming doesn't stop on it again straight away
+        resuming: bool,
+        // instructions left before stopping with BudgetExhausted, if there's a budget
+        budget: Option<u64>,
+        instructions: u64,
+        loop_detector: Option<LoopDetector<W>>,
+        profile: Option<Profile>,
+        coverage: Option<Coverage>,
+        tracer: Option<Tracer<W>>,
+        // the instruction being traced, filled in as it executes
+        trace_record: Option<TraceRecord<W>>,
+        undo_log: Option<UndoLog<W>>,
+        // how to undo the instruction being executed
+        undo_entry: Option<UndoEntry<W>>,
+        recording: Option<Recording<W>>,
+        decode_cache: Option<DecodeCache>,
+        // the instruction being executed
+        decoded: Decoded,
+        arithmetic: Arithmetic,
+        opcodes: OpcodeTable,
+        handlers: BTreeMap<i64, Handler<W>>,
+        strict: bool,
+        // how many words were loaded, which strict mode won't read past
+        image: usize,
     }
 
-    #[derive(Debug)]
-    pub enum RunSignal {
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub enum RunSignal<W = i64> {
         Halt,
         NoInput,
-        Output(i64),
+        Output(W),
+        Watch(WatchHit<W>),
+        // the id of the conditional breakpoint that stopped execution
+        Breakpoint(usize),
+        // the instruction budget ran out, top it up to carry on
+        BudgetExhausted,
     }
 
-    impl Emulator {
-        pub fn new(program: Vec<i64>, inputs: Vec<i64>, debug: bool) -> Emulator {
-            let mut memory = program.clone();
-            memory.append(&mut vec![0; 10000]);
+    impl<W: Word> Emulator<W> {
+        // debug no longer does anything by itself, it's kept so callers don't have to change.
+        // To see every instruction, set a tracer with somewhere to send the trace
+        pub fn new(program: Vec<W>, inputs: Vec<W>, _debug: bool) -> Emulator<W> {
+            let image = program.len();
             Emulator {
                 pc: 0,
                 relative_base: 0,
-                debug,
-                program: memory,
+                program: Memory::from(program),
                 inputs,
                 outputs: vec![],
                 is_halted: false,
+                watchpoints: vec![],
+                breakpoints: vec![],
+                next_id: 0,
+                watch_hits: VecDeque::new(),
+                pending_signal: None,
+                resuming: false,
+                budget: None,
+                instructions: 0,
+                loop_detector: None,
+                profile: None,
+                coverage: None,
+                tracer: None,
+                trace_record: None,
+                undo_log: None,
+                undo_entry: None,
+                recording: None,
+                decode_cache: None,
+                decoded: Decoded::new(0),
+                arithmetic: Arithmetic::default(),
+                opcodes: OpcodeTable::new(),
+                handlers: BTreeMap::new(),
+                strict: false,
+                image,
+            }
+        }
+
+        // overflow is an error unless the emulator is built to wrap or saturate instead
+        pub fn with_arithmetic(self, arithmetic: Arithmetic) -> Emulator<W> {
+            Emulator { arithmetic, ..self }
+        }
+
+        fn trace<F: FnOnce(&mut TraceRecord<W>)>(&mut self, update: F) {
+            if let Some(record) = &mut self.trace_record {
+                update(record);
             }
         }
 
-        fn print_debug(&self, statement: String) {
-            if self.debug {
-                println!("{}", statement);
+        fn fault(&self, kind: ErrorKind, parameter: Option<usize>) -> IntcodeError {
+            let instruction = self
+                .program
+                .get(self.pc)
+                .map_or(0, |word| word.saturating_i64());
+            IntcodeError {
+                kind,
+                pc: self.pc,
+                instruction,
+                parameter,
+                mode: parameter.map(|index| mode_digit(instruction, index)),
+                loop_pcs: None,
+                operands: Box::new([]),
+                message: None,
             }
         }
 
-        fn get_opcode(&self) -> i64 {
-            get_opcode(self.program[self.pc])
+        fn violation(
+            &self,
+            kind: ErrorKind,
+            parameter: Option<usize>,
+            message: String,
+        ) -> IntcodeError {
+            IntcodeError {
+                message: Some(message.into()),
+                ..self.fault(kind, parameter)
+            }
+        }
+
+        fn overflow(&self, val1: &W, val2: &W) -> IntcodeError {
+            IntcodeError {
+                operands: Box::new([val1.to_string(), val2.to_string()]),
+                ..self.fault(ErrorKind::Overflow, None)
+            }
+        }
+
+        fn read(&self, address: usize, parameter: Option<usize>) -> Result<W, IntcodeError> {
+            if self.strict && address >= self.image {
+                let message = format!(
+                    "address {} is outside the {} word program image",
+                    address, self.image
+                );
+                return Err(self.violation(ErrorKind::OutsideImage, parameter, message));
+            }
+            match self.program.get(address) {
+                Some(value) => Ok(value),
+                None => Err(self.fault(ErrorKind::OutOfBounds, parameter)),
+            }
+        }
+
+        fn write(&mut self, address: usize, value: W, index: usize) -> Result<(), IntcodeError> {
+            if let Some(detector) = &mut self.loop_detector {
+                detector.touch(address);
+            }
+            if let Some(cache) = &mut self.decode_cache {
+                cache.invalidate(address);
+            }
+            if let (Some(entry), Some(old)) = (&mut self.undo_entry, self.program.get(address)) {
+                entry.writes.push(MemoryWrite {
+                    address,
+                    value: old,
+                });
+            }
+            match self.program.set(address, value.clone()) {
+                true => {
+                    self.trace(|record| record.writes.push(MemoryWrite { address, value }));
+                    Ok(())
+                }
+                false => Err(self.fault(ErrorKind::OutOfBounds, Some(index))),
+            }
+        }
+
+        // anything too wide for an i64 is as out of bounds as an address gets
+        fn to_i64(&self, value: &W, index: usize) -> Result<i64, IntcodeError> {
+            match value.to_i64() {
+                Some(value) => Ok(value),
+                None if value.is_negative() => {
+                    Err(self.fault(ErrorKind::NegativeAddress, Some(index)))
+                }
+                None => Err(self.fault(ErrorKind::OutOfBounds, Some(index))),
+            }
+        }
+
+        fn to_address(&self, value: &W, index: usize) -> Result<usize, IntcodeError> {
+            match self.to_i64(value, index)? {
+                value if value < 0 => Err(self.fault(ErrorKind::NegativeAddress, Some(index))),
+                value => Ok(value as usize),
+            }
         }
 
-        fn decode_parameter(&self, index: usize) -> Mode {
-            decode_parameter(self.program[self.pc], index)
+        fn decode(&mut self) -> Result<Decoded, IntcodeError> {
+            let word = match self.read(self.pc, None)?.to_i64() {
+                Some(word) => word,
+                None => return Err(self.fault(ErrorKind::InvalidOpcode, None)),
+            };
+            Ok(match &mut self.decode_cache {
+                Some(cache) => cache.decode(self.pc, word),
+                None => Decoded::new(word),
+            })
         }
 
-        fn get_parameter(&self, index: usize) -> i64 {
-            match self.decode_parameter(index) {
+        fn decode_parameter(&self, index: usize) -> Result<Mode, IntcodeError> {
+            match self.decoded.mode(index) {
+                Some(mode) => Ok(mode),
+                None => Err(self.fault(ErrorKind::InvalidMode, Some(index))),
+            }
+        }
+
+        fn get_parameter(&mut self, index: usize) -> Result<W, IntcodeError> {
+            let value = match self.decode_parameter(index)? {
                 Mode::Position => self.get_positional(index),
                 Mode::Immediate => self.get_immediate(index),
                 Mode::Relative => self.get_relative(index),
+            }?;
+            if let Some(record) = &mut self.trace_record {
+                record.operands.push(value.clone());
             }
+            Ok(value)
         }
 
-        fn set_parameter(&mut self, index: usize, value: i64) {
-            match self.decode_parameter(index) {
+        fn set_parameter(&mut self, index: usize, value: W) -> Result<(), IntcodeError> {
+            match self.decode_parameter(index)? {
                 Mode::Position => self.set_positional(index, value),
-                Mode::Immediate => panic!("IMMEDIATE MODE NOT SUPPORTED FOR WRITING VALUES"),
-                Mode::Relative => self.set_relative(index, value)
+                Mode::Immediate => Err(self.fault(ErrorKind::WriteToImmediate, Some(index))),
+                Mode::Relative => self.set_relative(index, value),
             }
         }
 
-        fn get_immediate(&self, index: usize) -> i64 {
-            self.print_debug(format!("get_immediate {}", index));
-            self.program[self.pc + index]
+        fn get_immediate(&self, index: usize) -> Result<W, IntcodeError> {
+            self.read(self.pc + index, Some(index))
         }
 
-        fn get_positional(&self, index: usize) -> i64 {
-            let x = self.program[self.pc + index] as usize;
-            self.print_debug(format!("get_positional [{}] -> {}", index, x));
-            self.program[x]
+        fn get_positional(&mut self, index: usize) -> Result<W, IntcodeError> {
+            let x = self.to_address(&self.read(self.pc + index, Some(index))?, index)?;
+            let value = self.read(x, Some(index))?;
+            self.accessed(x, Access::Read, &value);
+            Ok(value)
         }
 
-        fn get_relative(&self, index: usize) -> i64 {
-            let x = self.program[self.pc + index];
-            let oldrel = self.relative_base.clone();
-            let relative_index = add_i64_to_usize(x, self.relative_base);
-            self.print_debug(format!(
-                "get_relative [{} + {}] -> {}",
-                oldrel, x, relative_index
-            ));
-            self.program[relative_index]
+        fn relative_address(&self, index: usize) -> Result<usize, IntcodeError> {
+            let x = self.to_i64(&self.read(self.pc + index, Some(index))?, index)?;
+            match add_i64_to_usize(x, self.relative_base) {
+                Some(relative_index) => Ok(relative_index),
+                None => Err(self.fault(ErrorKind::NegativeAddress, Some(index))),
+            }
         }
 
-        fn set_positional(&mut self, index: usize, value: i64) {
-            self.print_debug(format!("set_positional [{}] <- {}", index, value));
-            let x = self.program[self.pc + index] as usize;
-            self.program[x] = value;
+        fn get_relative(&mut self, index: usize) -> Result<W, IntcodeError> {
+            let relative_index = self.relative_address(index)?;
+            let value = self.read(relative_index, Some(index))?;
+            self.accessed(relative_index, Access::Read, &value);
+            Ok(value)
         }
 
-        fn set_relative(&mut self, index: usize, value: i64) {
-            let x = self.program[self.pc + index];
-            let oldrel = self.relative_base.clone();
-            let relative_index = add_i64_to_usize(x, self.relative_base);
-            self.print_debug(format!(
-                "set_relative [{} + {} = {}] <- {}",
-                oldrel, x, relative_index, value
-            ));
-            self.program[relative_index] = value;
+        fn set_positional(&mut self, index: usize, value: W) -> Result<(), IntcodeError> {
+            let x = self.to_address(&self.read(self.pc + index, Some(index))?, index)?;
+            self.write(x, value.clone(), index)?;
+            self.accessed(x, Access::Write, &value);
+            Ok(())
         }
 
-        pub fn run_program(&mut self) -> RunSignal {
-            if self.is_halted {
-                return RunSignal::Halt;
+        fn set_relative(&mut self, index: usize, value: W) -> Result<(), IntcodeError> {
+            let relative_index = self.relative_address(index)?;
+            self.write(relative_index, value.clone(), index)?;
+            self.accessed(relative_index, Access::Write, &value);
+            Ok(())
+        }
+
+        // a parameter read or written as data, rather than fetched as part of the instruction
+        fn accessed(&mut self, address: usize, access: Access, value: &W) {
+            if let Some(coverage) = &mut self.coverage {
+                coverage.record_access(address, access);
+            }
+            for watchpoint in &self.watchpoints {
+                if watchpoint.access.covers(access) && watchpoint.addresses.contains(&address) {
+                    self.watch_hits.push_back(WatchHit {
+                        watchpoint: watchpoint.id,
+                        pc: self.pc,
+                        address,
+                        access,
+                        value: value.clone(),
+                    });
+                }
+            }
+        }
+
+        // watch a range of addresses, returning an id to remove it with
+        pub fn add_watchpoint(
+            &mut self,
+            addresses: RangeInclusive<usize>,
+            access: Access,
+        ) -> usize {
+            self.next_id += 1;
+            self.watchpoints.push(Watchpoint {
+                id: self.next_id,
+                addresses,
+                access,
+            });
+            self.next_id
+        }
+
+        pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+            let count = self.watchpoints.len();
+            self.watchpoints.retain(|w| w.id != id);
+            count != self.watchpoints.len()
+        }
+
+        pub fn watchpoints(&self) -> &[Watchpoint] {
+            &self.watchpoints
+        }
+
+        // stop before any instruction where the condition holds, returning an id to remove it with
+        pub fn add_breakpoint(&mut self, condition: Condition) -> usize {
+            self.next_id += 1;
+            self.breakpoints.push(Breakpoint {
+                id: self.next_id,
+                condition,
+            });
+            self.next_id
+        }
+
+        pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+            let count = self.breakpoints.len();
+            self.breakpoints.retain(|b| b.id != id);
+            count != self.breakpoints.len()
+        }
+
+        pub fn breakpoints(&self) -> &[Breakpoint] {
+            &self.breakpoints
+        }
+
+        pub fn pc(&self) -> usize {
+            self.pc
+        }
+
+        pub fn relative_base(&self) -> usize {
+            self.relative_base
+        }
+
+        pub fn is_halted(&self) -> bool {
+            self.is_halted
+        }
+
+        pub fn peek(&self, address: usize) -> Option<W> {
+            self.program.get(address)
+        }
+
+        pub fn poke(&mut self, address: usize, value: W) -> bool {
+            self.reset_loop_detector();
+            self.forget_history();
+            self.program.set(address, value)
+        }
+
+        // memory grows as far as the program likes unless it's given a limit
+        pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+            self.program.set_limit(limit);
+        }
+
+        pub fn arithmetic(&self) -> Arithmetic {
+            self.arithmetic
+        }
+
+        pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+            self.arithmetic = arithmetic;
+        }
+
+        // run a handler for an opcode the emulator doesn't know, see crate::opcodes
+        pub fn add_opcode<F>(&mut self, info: OpcodeInfo, handler: F) -> Result<(), OpcodeError>
+        where
+            F: Fn(&mut Operation<W>) -> Result<(), String> + Send + Sync + 'static,
+        {
+            self.opcodes.register(info)?;
+            self.handlers.insert(info.opcode, Arc::new(handler));
+            Ok(())
+        }
+
+        // the built in opcodes and any that have been added
+        pub fn opcodes(&self) -> &OpcodeTable {
+            &self.opcodes
+        }
+
+        // reject what the spec leaves undefined: writes to immediate parameters however they're
+        // reached, mode digits past an opcode's parameters, jumps to negative targets (taken or
+        // not) and reads past the end of the program as loaded
+        pub fn set_strict(&mut self, strict: bool) {
+            self.strict = strict;
+        }
+
+        pub fn is_strict(&self) -> bool {
+            self.strict
+        }
+
+        // how many words the program was loaded with
+        pub fn image(&self) -> usize {
+            self.image
+        }
+
+        // strict mode's checks on the instruction word alone, before anything is executed
+        fn check_instruction(&self) -> Result<(), IntcodeError> {
+            let info = match self.opcodes.info(self.decoded.opcode) {
+                Some(info) => info,
+                // not an opcode at all, which execute reports
+                None => return Ok(()),
+            };
+            if let Some(index) = info.writes {
+                if self.decoded.mode(index) == Some(Mode::Immediate) {
+                    let message = format!(
+                        "{} writes to parameter {}, which can't be immediate",
+                        info.mnemonic, index
+                    );
+                    return Err(self.violation(ErrorKind::WriteToImmediate, Some(index), message));
+                }
+            }
+            // the digits above the last parameter's mode should all be zero
+            let mut rest = self.decoded.word / 10_i64.pow(info.parameters as u32 + 2);
+            if rest != 0 {
+                let mut index = info.parameters + 1;
+                while rest % 10 == 0 {
+                    rest /= 10;
+                    index += 1;
+                }
+                let message = format!(
+                    "{} takes {} parameters, but has a mode for parameter {}",
+                    info.mnemonic, info.parameters, index
+                );
+                return Err(IntcodeError {
+                    mode: Some(rest % 10),
+                    ..self.violation(ErrorKind::StrayMode, Some(index), message)
+                });
+            }
+            Ok(())
+        }
+
+        // a jump's target has to make sense in strict mode even when the jump isn't taken
+        fn check_target(&self, target: &W) -> Result<(), IntcodeError> {
+            if self.strict && target.is_negative() {
+                let message = format!("jump target {} is negative", target);
+                return Err(self.violation(ErrorKind::NegativeAddress, Some(2), message));
+            }
+            Ok(())
+        }
+
+        pub fn instruction_budget(&self) -> Option<u64> {
+            self.budget
+        }
+
+        // how many more instructions to execute before stopping with BudgetExhausted
+        pub fn set_instruction_budget(&mut self, budget: Option<u64>) {
+            self.budget = budget;
+        }
+
+        pub fn instructions_executed(&self) -> u64 {
+            self.instructions
+        }
+
+        // report a program stuck going round the same loop forever as an InfiniteLoop error
+        pub fn set_loop_detection(&mut self, enabled: bool) {
+            self.loop_detector = match enabled {
+                true => Some(LoopDetector::default()),
+                false => None,
+            };
+        }
+
+        // start counting what every instruction does from here on, or stop and throw the counts away
+        pub fn set_profiling(&mut self, enabled: bool) {
+            self.profile = match enabled {
+                true => Some(Profile::default()),
+                false => None,
+            };
+        }
+
+        pub fn profile(&self) -> Option<&Profile> {
+            self.profile.as_ref()
+        }
+
+        // start recording which addresses are executed, read and written, or stop and forget them
+        pub fn set_coverage(&mut self, enabled: bool) {
+            self.coverage = match enabled {
+                true => Some(Coverage::default()),
+                false => None,
+            };
+        }
+
+        pub fn coverage(&self) -> Option<&Coverage> {
+            self.coverage.as_ref()
+        }
+
+        // every instruction that passes the tracer's filter is sent to its sink
+        pub fn set_tracer(&mut self, tracer: Option<Tracer<W>>) {
+            self.tracer = tracer;
+        }
+
+        pub fn tracer(&self) -> Option<&Tracer<W>> {
+            self.tracer.as_ref()
+        }
+
+        // cache decoded instructions by address. Every fetch still reads the word to check it
+        // hasn't changed, so this only pays off on long runs of tight loops, and is off by default
+        pub fn set_decode_cache(&mut self, enabled: bool) {
+            self.decode_cache = match enabled {
+                true => Some(DecodeCache::new()),
+                false => None,
+            };
+        }
+
+        pub fn decode_cache(&self) -> Option<&DecodeCache> {
+            self.decode_cache.as_ref()
+        }
+
+        // start recording every input taken and output made, or stop and throw the recording away
+        pub fn set_recording(&mut self, enabled: bool) {
+            self.recording = match enabled {
+                true => Some(Recording::new()),
+                false => None,
+            };
+        }
+
+        pub fn recording(&self) -> Option<&Recording<W>> {
+            self.recording.as_ref()
+        }
+
+        // keep enough to undo the last capacity instructions, or stop keeping anything
+        pub fn set_undo_log(&mut self, capacity: Option<usize>) {
+            self.undo_log = capacity.map(UndoLog::new);
+        }
+
+        pub fn undo_log(&self) -> Option<&UndoLog<W>> {
+            self.undo_log.as_ref()
+        }
+
+        // how many instructions can be stepped back over
+        pub fn undo_depth(&self) -> usize {
+            self.undo_log.as_ref().map_or(0, |log| log.len())
+        }
+
+        // changes made from outside aren't in the log, so undoing past them would make no sense
+        fn forget_history(&mut self) {
+            if let Some(log) = &mut self.undo_log {
+                log.clear();
+            }
+        }
+
+        // undo the last instruction executed, returning what it did. Profiles, coverage and
+        // traces still count it, and the instruction budget isn't given back
+        pub fn step_back(&mut self) -> Option<UndoEntry<W>> {
+            let entry = self.undo_log.as_mut()?.pop()?;
+            for write in entry.writes.iter().rev() {
+                self.program.set(write.address, write.value.clone());
             }
+            self.program.lower_high_water_mark(entry.high_water_mark);
+            self.pc = entry.pc;
+            self.relative_base = entry.relative_base;
+            if let Some(input) = &entry.input {
+                self.inputs.insert(0, input.clone());
+            }
+            if entry.output.is_some() {
+                self.outputs.pop();
+            }
+            if entry.input.is_some() || entry.output.is_some() {
+                if let Some(recording) = &mut self.recording {
+                    recording.events.pop();
+                }
+            }
+            self.is_halted = false;
+            self.instructions -= 1;
+            self.watch_hits.clear();
+            self.pending_signal = None;
+            // don't stop on a breakpoint we've just stepped back onto when going forward again
+            self.resuming = true;
+            self.reset_loop_detector();
+            Some(entry)
+        }
+
+        // step back until just before the latest instruction that matches, returning how many
+        // steps that took, or None without moving if it's further back than the log goes
+        fn step_back_to<P: Fn(&UndoEntry<W>) -> bool>(&mut self, predicate: P) -> Option<usize> {
+            let steps = self.undo_log.as_ref()?.steps_back_to(predicate)?;
+            for _ in 0..steps {
+                self.step_back();
+            }
+            Some(steps)
+        }
+
+        // back to the instruction that last wrote to an address
+        pub fn back_to_write(&mut self, address: usize) -> Option<usize> {
+            self.step_back_to(|entry| entry.wrote(address))
+        }
+
+        // back to just before the last input was taken, which goes back on the front of inputs
+        pub fn back_to_input(&mut self) -> Option<usize> {
+            self.step_back_to(|entry| entry.input.is_some())
+        }
+
+        fn reset_loop_detector(&mut self) {
+            if let Some(detector) = &mut self.loop_detector {
+                detector.reset();
+            }
+        }
+
+        // having just come back round to a state first seen some steps ago, go round once more
+        // to see where the loop goes. That should take the same number of steps again, but a
+        // custom handler needn't do the same thing twice, so it doesn't get any more than that
+        fn infinite_loop(&self, steps: u64) -> IntcodeError {
+            // just the machine, none of the history, profiling and the like
+            let mut emulator = Emulator {
+                pc: self.pc,
+                relative_base: self.relative_base,
+                program: self.program.clone(),
+                arithmetic: self.arithmetic,
+                opcodes: self.opcodes.clone(),
+                handlers: self.handlers.clone(),
+                strict: self.strict,
+                image: self.image,
+                ..Emulator::new(vec![], vec![], false)
+            };
+            let mut pcs = self.pc..=self.pc;
+            for _ in 0..steps {
+                let pc = emulator.pc;
+                pcs = *pcs.start().min(&pc)..=*pcs.end().max(&pc);
+                match emulator.execute() {
+                    Err(error) => return error,
+                    // halting or wanting input isn't going round in a loop after all
+                    Ok(Some(_)) => break,
+                    Ok(None) => {}
+                }
+                if emulator.pc <= pc
+                    && emulator.pc == self.pc
+                    && emulator.relative_base == self.relative_base
+                    && emulator.program == self.program
+                {
+                    break;
+                }
+            }
+            IntcodeError {
+                loop_pcs: Some(pcs),
+                ..self.fault(ErrorKind::InfiniteLoop, None)
+            }
+        }
+
+        pub fn from_snapshot(snapshot: &Snapshot<W>, debug: bool) -> Emulator<W> {
+            let mut emulator = Emulator::new(vec![], vec![], debug);
+            emulator.restore(snapshot);
+            emulator
+        }
+
+        pub fn snapshot(&self) -> Snapshot<W> {
+            Snapshot {
+                memory: self.program.clone(),
+                pc: self.pc,
+                relative_base: self.relative_base,
+                inputs: self.inputs.clone(),
+                outputs: self.outputs.clone(),
+                is_halted: self.is_halted,
+                image: self.image,
+            }
+        }
+
+        pub fn restore(&mut self, snapshot: &Snapshot<W>) {
+            // the memory limit is a setting of this emulator rather than part of its state
+            let limit = self.program.limit();
+            self.program = snapshot.memory.clone();
+            self.program.set_limit(limit);
+            self.pc = snapshot.pc;
+            self.relative_base = snapshot.relative_base;
+            self.inputs = snapshot.inputs.clone();
+            self.outputs = snapshot.outputs.clone();
+            self.is_halted = snapshot.is_halted;
+            self.image = snapshot.image;
+            // anything waiting to be reported belongs to the state we've just left
+            self.watch_hits.clear();
+            self.pending_signal = None;
+            self.resuming = false;
+            self.reset_loop_detector();
+            self.forget_history();
+        }
+
+        pub fn run_program(&mut self) -> Result<RunSignal<W>, IntcodeError> {
+            // the caller may have changed anything since the last run
+            self.reset_loop_detector();
             loop {
-                let opcode = self.get_opcode();
-                /* self.print_debug(format!(
-                    "OPCODE {}",
-                    opcode
-                )); */
-                match opcode {
-                    1 => self.add(),
-                    2 => self.multiply(),
-                    3 => {
-                        if !self.input() {
-                            return RunSignal::NoInput;
-                        }
+                if let Some(signal) = self.step()? {
+                    return Ok(signal);
+                }
+            }
+        }
+
+        // like run_program, but pulls inputs from a source as they're needed and passes every
+        // output on to a sink, so it only stops to halt, when the source runs dry, or to debug
+        pub fn run_with<I, O>(
+            &mut self,
+            input: &mut I,
+            output: &mut O,
+        ) -> Result<RunSignal<W>, IntcodeError>
+        where
+            I: InputSource<W> + ?Sized,
+            O: OutputSink<W> + ?Sized,
+        {
+            loop {
+                match self.run_program()? {
+                    RunSignal::NoInput => match input.next_input() {
+                        Some(value) => self.inputs.push(value),
+                        None => return Ok(RunSignal::NoInput),
+                    },
+                    RunSignal::Output(value) => output.write_output(value),
+                    signal => return Ok(signal),
+                }
+            }
+        }
+
+        // execute a single instruction, returning a signal if it needs the caller's attention
+        pub fn step(&mut self) -> Result<Option<RunSignal<W>>, IntcodeError> {
+            if let Some(hit) = self.watch_hits.pop_front() {
+                return Ok(Some(RunSignal::Watch(hit)));
+            }
+            if let Some(signal) = self.pending_signal.take() {
+                return Ok(Some(signal));
+            }
+            if self.is_halted {
+                return Ok(Some(RunSignal::Halt));
+            }
+            if !self.resuming {
+                let triggered = self.breakpoints.iter().find(|b| b.condition.is_met(self));
+                if let Some(breakpoint) = triggered {
+                    self.resuming = true;
+                    return Ok(Some(RunSignal::Breakpoint(breakpoint.id)));
+                }
+            }
+            if self.budget == Some(0) {
+                return Ok(Some(RunSignal::BudgetExhausted));
+            }
+            let pc = self.pc;
+            let tracing = self.tracer.is_some();
+            // read before executing, in case the instruction overwrites itself
+            let instruction = match self.profile.is_some() || self.coverage.is_some() || tracing {
+                true => self.program[pc].saturating_i64(),
+                false => 0,
+            };
+            if tracing {
+                let info = self.opcodes.info(get_opcode(instruction));
+                self.trace_record = Some(TraceRecord::with_info(
+                    self.instructions,
+                    pc,
+                    instruction,
+                    info,
+                ));
+            }
+            if self.undo_log.is_some() {
+                let high_water_mark = self.program.high_water_mark();
+                self.undo_entry = Some(UndoEntry::new(pc, self.relative_base, high_water_mark));
+            }
+            let result = self.execute();
+            let record = self.trace_record.take();
+            let entry = self.undo_entry.take();
+            let signal = result?;
+            if signal != Some(RunSignal::NoInput) {
+                self.resuming = false;
+                self.instructions += 1;
+                self.budget = self.budget.map(|budget| budget - 1);
+                if let Some(profile) = &mut self.profile {
+                    profile.record(pc, instruction, self.pc, &self.opcodes);
+                }
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.record_instruction(pc, instruction, self.pc, &self.opcodes);
+                }
+                if let (Some(log), Some(mut entry)) = (&mut self.undo_log, entry) {
+                    entry.halted = self.is_halted;
+                    log.push(entry);
+                }
+                if let Some(mut record) = record {
+                    record.next_pc = self.pc;
+                    if let Some(tracer) = &self.tracer {
+                        tracer.record(&record);
                     }
-                    4 => {
-                        self.output();
-                        let last_output = self.outputs[self.outputs.len() - 1];
-                        return RunSignal::Output(last_output);
+                }
+            }
+            if signal.is_none() && self.pc <= pc {
+                let (pc, relative_base) = (self.pc, self.relative_base);
+                let first_seen = match &mut self.loop_detector {
+                    Some(detector) => {
+                        detector.visit(pc, relative_base, &self.program, self.instructions)
                     }
-                    5 => self.jump_if_true(),
-                    6 => self.jump_if_false(),
-                    7 => self.less_than(),
-                    8 => self.equals(),
-                    9 => self.adjust_relative_base(),
-                    99 => {
-                        self.is_halted = true;
-                        return RunSignal::Halt; //HALT!
+                    None => None,
+                };
+                if let Some(first_seen) = first_seen {
+                    return Err(self.infinite_loop(self.instructions - first_seen));
+                }
+            }
+            match self.watch_hits.pop_front() {
+                Some(hit) => {
+                    self.pending_signal = signal;
+                    Ok(Some(RunSignal::Watch(hit)))
+                }
+                None => Ok(signal),
+            }
+        }
+
+        fn execute(&mut self) -> Result<Option<RunSignal<W>>, IntcodeError> {
+            self.decoded = self.decode()?;
+            if self.strict {
+                self.check_instruction()?;
+            }
+            match self.decoded.opcode {
+                1 => self.add()?,
+                2 => self.multiply()?,
+                3 => {
+                    if !self.input()? {
+                        return Ok(Some(RunSignal::NoInput));
                     }
-                    _ => panic!("UNEXPECTED OPCODE '{}'", opcode),
+                    self.reset_loop_detector();
+                }
+                4 => {
+                    let val = self.get_parameter(1)?;
+                    self.output(val.clone());
+                    self.pc += 2;
+                    return Ok(Some(RunSignal::Output(val)));
+                }
+                5 => self.jump_if_true()?,
+                6 => self.jump_if_false()?,
+                7 => self.less_than()?,
+                8 => self.equals()?,
+                9 => self.adjust_relative_base()?,
+                99 => {
+                    self.is_halted = true;
+                    return Ok(Some(RunSignal::Halt)); //HALT!
                 }
+                opcode => match self.handlers.get(&opcode) {
+                    Some(handler) => return self.custom(handler.clone()),
+                    None => return Err(self.fault(ErrorKind::InvalidOpcode, None)),
+                },
+            }
+            Ok(None)
+        }
+
+        // an opcode added with add_opcode
+        fn custom(&mut self, handler: Handler<W>) -> Result<Option<RunSignal<W>>, IntcodeError> {
+            let info = match self.opcodes.info(self.decoded.opcode) {
+                Some(info) => info,
+                None => return Err(self.fault(ErrorKind::InvalidOpcode, None)),
+            };
+            let mut operands = vec![];
+            for index in 1..=info.parameters {
+                match info.writes == Some(index) {
+                    // check it can be written before the handler does anything
+                    true => {
+                        if self.decode_parameter(index)? == Mode::Immediate {
+                            return Err(self.fault(ErrorKind::WriteToImmediate, Some(index)));
+                        }
+                    }
+                    false => operands.push(self.get_parameter(index)?),
+                }
+            }
+            let mut operation = Operation::new(self.pc, operands);
+            if let Err(message) = handler(&mut operation) {
+                return Err(IntcodeError {
+                    message: Some(message.into()),
+                    ..self.fault(ErrorKind::HandlerFailed, None)
+                });
+            }
+            if let (Some(index), Some(result)) = (info.writes, operation.result) {
+                self.set_parameter(index, result)?;
             }
+            if let Some(val) = &operation.output {
+                self.output(val.clone());
+            }
+            self.pc = match operation.jump {
+                Some(target) => target,
+                None => self.pc + 1 + info.parameters,
+            };
+            Ok(operation.output.map(RunSignal::Output))
         }
 
-        fn add(&mut self) {
-            let val1 = self.get_parameter(1);
-            let val2 = self.get_parameter(2);
-            let res = val1 + val2;
-            self.print_debug(format!("ADD {} + {} = {}", val1, val2, res));
-            self.set_parameter(3, res);
+        fn add(&mut self) -> Result<(), IntcodeError> {
+            let val1 = self.get_parameter(1)?;
+            let val2 = self.get_parameter(2)?;
+            let res = match val1.add(&val2, self.arithmetic) {
+                Some(res) => res,
+                None => return Err(self.overflow(&val1, &val2)),
+            };
+            self.set_parameter(3, res)?;
             self.pc += 4;
+            Ok(())
         }
 
-        fn multiply(&mut self) {
-            let val1 = self.get_parameter(1);
-            let val2 = self.get_parameter(2);
-            let res = val1 * val2;
-            self.print_debug(format!("MUL {} * {} = {}", val1, val2, res));
-            self.set_parameter(3, res);
+        fn multiply(&mut self) -> Result<(), IntcodeError> {
+            let val1 = self.get_parameter(1)?;
+            let val2 = self.get_parameter(2)?;
+            let res = match val1.mul(&val2, self.arithmetic) {
+                Some(res) => res,
+                None => return Err(self.overflow(&val1, &val2)),
+            };
+            self.set_parameter(3, res)?;
             self.pc += 4;
+            Ok(())
         }
 
-        fn input(&mut self) -> bool {
-            if self.inputs.len() == 0 {
-                return false; //signal we need more input!
+        fn input(&mut self) -> Result<bool, IntcodeError> {
+            if self.inputs.is_empty() {
+                return Ok(false); //signal we need more input!
+            }
+            // check the destination before consuming, so a bad write doesn't eat the input
+            self.decode_parameter(1)?;
+            let val = self.inputs[0].clone();
+            self.set_parameter(1, val.clone())?;
+            if let Some(record) = &mut self.trace_record {
+                record.input = Some(val.clone());
+            }
+            if let Some(entry) = &mut self.undo_entry {
+                entry.input = Some(val.clone());
             }
-            let val: i64 = self.inputs.remove(0);
-            self.print_debug(format!("INPUT {}", val));
-            self.set_parameter(1, val);
+            let (pc, instructions) = (self.pc, self.instructions);
+            if let Some(recording) = &mut self.recording {
+                recording.events.push(Event::Input {
+                    value: val,
+                    pc,
+                    instructions,
+                });
+            }
+            self.inputs.remove(0);
             self.pc += 2;
-            return true;
+            Ok(true)
         }
 
-        fn output(&mut self) {
-            let val = self.get_parameter(1);
-            self.print_debug(format!("OUTPUT {}", val));
+        // everything that notes an output, for OUT and any custom opcode that outputs
+        fn output(&mut self, val: W) {
+            self.reset_loop_detector();
+            if let Some(record) = &mut self.trace_record {
+                record.output = Some(val.clone());
+            }
+            if let Some(entry) = &mut self.undo_entry {
+                entry.output = Some(val.clone());
+            }
+            let (pc, instructions) = (self.pc, self.instructions);
+            if let Some(recording) = &mut self.recording {
+                recording.events.push(Event::Output {
+                    value: val.clone(),
+                    pc,
+                    instructions,
+                });
+            }
             self.outputs.push(val);
-            self.pc += 2;
         }
 
-        fn jump_if_true(&mut self) {
-            let val1 = self.get_parameter(1);
-            let val2 = self.get_parameter(2);
-            self.print_debug(format!("JIT {} != 0 ? jump to {}", val1, val2));
-            if val1 != 0 {
-                self.pc = val2 as usize;
+        fn jump_if_true(&mut self) -> Result<(), IntcodeError> {
+            let val1 = self.get_parameter(1)?;
+            let val2 = self.get_parameter(2)?;
+            self.check_target(&val2)?;
+            if !val1.is_zero() {
+                self.pc = self.to_address(&val2, 2)?;
             } else {
                 self.pc += 3;
             }
+            Ok(())
         }
 
-        fn jump_if_false(&mut self) {
-            let val1 = self.get_parameter(1);
-            let val2 = self.get_parameter(2);
-            self.print_debug(format!("JIF {} == 0 ? jump to {}", val1, val2));
-            if val1 == 0 {
-                self.pc = val2 as usize;
+        fn jump_if_false(&mut self) -> Result<(), IntcodeError> {
+            let val1 = self.get_parameter(1)?;
+            let val2 = self.get_parameter(2)?;
+            self.check_target(&val2)?;
+            if val1.is_zero() {
+                self.pc = self.to_address(&val2, 2)?;
             } else {
                 self.pc += 3;
             }
+            Ok(())
         }
 
-        fn less_than(&mut self) {
-            let val1 = self.get_parameter(1);
-            let val2 = self.get_parameter(2);
-            self.print_debug(format!(
-                "LT {} < {} ?",
-                val1, val2
-            ));
-            if val1 < val2 {
-                self.set_parameter(3, 1);
-            } else {
-                self.set_parameter(3, 0);
-            }
+        fn less_than(&mut self) -> Result<(), IntcodeError> {
+            let val1 = self.get_parameter(1)?;
+            let val2 = self.get_parameter(2)?;
+            self.set_parameter(3, W::from_bool(val1 < val2))?;
             self.pc += 4;
+            Ok(())
         }
 
-        fn equals(&mut self) {
-            let val1 = self.get_parameter(1);
-            let val2 = self.get_parameter(2);
-            self.print_debug(format!(
-                "EQ {} == {}",
-                val1, val2
-            ));
-            if val1 == val2 {
-                self.set_parameter(3, 1);
-            } else {
-                self.set_parameter(3, 0);
-            }
+        fn equals(&mut self) -> Result<(), IntcodeError> {
+            let val1 = self.get_parameter(1)?;
+            let val2 = self.get_parameter(2)?;
+            self.set_parameter(3, W::from_bool(val1 == val2))?;
             self.pc += 4;
+            Ok(())
         }
 
-        fn adjust_relative_base(&mut self) {
-            let val1 = self.get_parameter(1);
-            let oldrel = self.relative_base.clone();
-            self.relative_base = add_i64_to_usize(val1, self.relative_base);
-            self.print_debug(format!(
-                "ADJUST REL {} + {} = {}",
-                oldrel, val1, self.relative_base
-            ));
+        fn adjust_relative_base(&mut self) -> Result<(), IntcodeError> {
+            let val1 = self.get_parameter(1)?;
+            let val1 = self.to_i64(&val1, 1)?;
+            self.relative_base = match add_i64_to_usize(val1, self.relative_base) {
+                Some(relative_base) => relative_base,
+                None => return Err(self.fault(ErrorKind::NegativeAddress, Some(1))),
+            };
+            let relative_base = self.relative_base;
+            self.trace(|record| record.relative_base = Some(relative_base));
             self.pc += 2;
+            Ok(())
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::assembler::assemble_to_string;
     use crate::intcode::*;
+    use crate::word::BigWord;
 
     #[test]
     fn run_program_works_1() {
@@ -391,7 +1355,7 @@ mod tests {
     ) {
         let mut emulator = prepare_emulator(program_spec, input_spec, true);
         loop {
-            match emulator.run_program() {
+            match emulator.run_program().unwrap() {
                 RunSignal::Halt => break,
                 _ => continue,
             }
@@ -402,6 +1366,107 @@ mod tests {
         assert_eq!(expected_output, output);
     }
 
+    #[test]
+    fn watchpoints_report_accesses() {
+        // out [7]; add [7], #1 -> [7]; hlt
+        let mut emulator = prepare_emulator("4,7,1001,7,1,7,99".to_string(), "".to_string(), false);
+        emulator.program[7] = 10;
+        let read = emulator.add_watchpoint(7..=7, Access::Read);
+        let write = emulator.add_watchpoint(0..=100, Access::Write);
+        let hit = |watchpoint, pc, access, value| {
+            RunSignal::Watch(WatchHit {
+                watchpoint,
+                pc,
+                address: 7,
+                access,
+                value,
+            })
+        };
+
+        // the output is held back until the watch has been reported
+        assert_eq!(
+            hit(read, 0, Access::Read, 10),
+            emulator.run_program().unwrap()
+        );
+        assert_eq!(RunSignal::Output(10), emulator.run_program().unwrap());
+        assert_eq!(
+            hit(read, 2, Access::Read, 10),
+            emulator.run_program().unwrap()
+        );
+        assert_eq!(
+            hit(write, 2, Access::Write, 11),
+            emulator.run_program().unwrap()
+        );
+        assert!(emulator.remove_watchpoint(read));
+        assert!(!emulator.remove_watchpoint(read));
+        assert_eq!(1, emulator.watchpoints().len());
+        assert_eq!(RunSignal::Halt, emulator.run_program().unwrap());
+    }
+
+    #[test]
+    fn conditional_breakpoints_stop_before_the_instruction() {
+        // loop: add [9], #1 -> [9]; jt #1, #loop ... until the breakpoint stops it
+        let mut emulator = prepare_emulator(
+            "1001,9,1,9,1105,1,0,99,0,0".to_string(),
+            "".to_string(),
+            false,
+        );
+        let condition = crate::condition::Condition::parse("pc == 0 && mem[9] >= 3").unwrap();
+        let id = emulator.add_breakpoint(condition);
+
+        assert_eq!(RunSignal::Breakpoint(id), emulator.run_program().unwrap());
+        assert_eq!(3, emulator.program[9]);
+        assert_eq!(0, emulator.pc());
+        // resuming steps past the breakpoint, which fires again next time round
+        assert_eq!(RunSignal::Breakpoint(id), emulator.run_program().unwrap());
+        assert_eq!(4, emulator.program[9]);
+
+        assert!(emulator.remove_breakpoint(id));
+        assert!(emulator.breakpoints().is_empty());
+        emulator.program[4] = 99;
+        assert_eq!(RunSignal::Halt, emulator.run_program().unwrap());
+    }
+
+    #[test]
+    fn snapshots_can_be_restored() {
+        // in -> [13]; out [13]; hlt ... echoes one input
+        let mut emulator = prepare_emulator("3,13,4,13,99".to_string(), "".to_string(), false);
+        assert_eq!(RunSignal::NoInput, emulator.run_program().unwrap());
+        let checkpoint = emulator.snapshot();
+
+        emulator.inputs.push(1);
+        assert_eq!(RunSignal::Output(1), emulator.run_program().unwrap());
+        assert_eq!(RunSignal::Halt, emulator.run_program().unwrap());
+        assert!(emulator.is_halted());
+
+        emulator.restore(&checkpoint);
+        assert!(!emulator.is_halted());
+        assert_eq!(checkpoint, emulator.snapshot());
+        emulator.inputs.push(2);
+        assert_eq!(RunSignal::Output(2), emulator.run_program().unwrap());
+        assert_eq!(vec![2], emulator.outputs);
+    }
+
+    #[test]
+    fn clones_fork_independently() {
+        let mut emulator = prepare_emulator("3,13,4,13,99".to_string(), "".to_string(), false);
+        emulator.run_program().unwrap();
+
+        let mut forks: Vec<Emulator> = (0..1000).map(|_| emulator.clone()).collect();
+        for (i, fork) in forks.iter_mut().enumerate() {
+            fork.inputs.push(i as i64);
+            assert_eq!(RunSignal::Output(i as i64), fork.run_program().unwrap());
+        }
+        // each fork only copied the page it wrote to
+        let pages = emulator.program.shared_pages(&emulator.program);
+        assert_eq!(
+            pages - 1,
+            forks[999].program.shared_pages(&emulator.program)
+        );
+        assert_eq!(0, emulator.program[13]);
+        assert_eq!(RunSignal::NoInput, emulator.run_program().unwrap());
+    }
+
     #[test]
     fn get_opcode_works() {
         assert_eq!(1, get_opcode(1));
@@ -411,33 +1476,390 @@ mod tests {
         assert_eq!(2, get_opcode(11102));
     }
 
+    #[test]
+    fn opcode_info_works() {
+        assert_eq!(Some(3), opcode_info(1).map(|info| info.parameters));
+        assert_eq!(Some(Some(1)), opcode_info(3).map(|info| info.writes));
+        assert_eq!(Some("HLT"), opcode_info(99).map(|info| info.mnemonic));
+        assert_eq!(None, opcode_info(42));
+    }
+
     #[test]
     fn decode_parameter_works() {
-        assert_eq!(Mode::Position, decode_parameter(1, 1));
-        assert_eq!(Mode::Position, decode_parameter(1, 2));
-        assert_eq!(Mode::Position, decode_parameter(1, 3));
-        assert_eq!(Mode::Position, decode_parameter(2, 1));
-        assert_eq!(Mode::Position, decode_parameter(2, 2));
-        assert_eq!(Mode::Position, decode_parameter(2, 3));
-        assert_eq!(Mode::Position, decode_parameter(10002, 1));
-        assert_eq!(Mode::Position, decode_parameter(10002, 2));
-        assert_eq!(Mode::Immediate, decode_parameter(102, 1));
-        assert_eq!(Mode::Immediate, decode_parameter(1002, 2));
-        assert_eq!(Mode::Immediate, decode_parameter(10002, 3));
-        assert_eq!(Mode::Relative, decode_parameter(202, 1));
-        assert_eq!(Mode::Relative, decode_parameter(2002, 2));
-        assert_eq!(Mode::Relative, decode_parameter(20002, 3));
-        assert_eq!(Mode::Immediate, decode_parameter(11102, 1));
-        assert_eq!(Mode::Immediate, decode_parameter(11102, 2));
-        assert_eq!(Mode::Immediate, decode_parameter(11102, 3));
-        assert_eq!(Mode::Immediate, decode_parameter(1102, 1));
-        assert_eq!(Mode::Immediate, decode_parameter(1102, 2));
-        assert_eq!(Mode::Position, decode_parameter(1102, 3));
+        assert_eq!(Ok(Mode::Position), decode_parameter(1, 1));
+        assert_eq!(Ok(Mode::Position), decode_parameter(1, 2));
+        assert_eq!(Ok(Mode::Position), decode_parameter(1, 3));
+        assert_eq!(Ok(Mode::Position), decode_parameter(2, 1));
+        assert_eq!(Ok(Mode::Position), decode_parameter(2, 2));
+        assert_eq!(Ok(Mode::Position), decode_parameter(2, 3));
+        assert_eq!(Ok(Mode::Position), decode_parameter(10002, 1));
+        assert_eq!(Ok(Mode::Position), decode_parameter(10002, 2));
+        assert_eq!(Ok(Mode::Immediate), decode_parameter(102, 1));
+        assert_eq!(Ok(Mode::Immediate), decode_parameter(1002, 2));
+        assert_eq!(Ok(Mode::Immediate), decode_parameter(10002, 3));
+        assert_eq!(Ok(Mode::Relative), decode_parameter(202, 1));
+        assert_eq!(Ok(Mode::Relative), decode_parameter(2002, 2));
+        assert_eq!(Ok(Mode::Relative), decode_parameter(20002, 3));
+        assert_eq!(Ok(Mode::Immediate), decode_parameter(11102, 1));
+        assert_eq!(Ok(Mode::Immediate), decode_parameter(11102, 2));
+        assert_eq!(Ok(Mode::Immediate), decode_parameter(11102, 3));
+        assert_eq!(Ok(Mode::Immediate), decode_parameter(1102, 1));
+        assert_eq!(Ok(Mode::Immediate), decode_parameter(1102, 2));
+        assert_eq!(Ok(Mode::Position), decode_parameter(1102, 3));
     }
 
     #[test]
     fn add_i64_to_usize_works() {
-        assert_eq!(0, add_i64_to_usize(-1, 1));
-        assert_eq!(1, add_i64_to_usize(1, 0));
+        assert_eq!(Some(0), add_i64_to_usize(-1, 1));
+        assert_eq!(Some(1), add_i64_to_usize(1, 0));
+        assert_eq!(None, add_i64_to_usize(-2, 1));
+    }
+
+    #[test]
+    fn decode_mode_rejects_unknown_modes() {
+        assert_eq!(Err(ErrorKind::InvalidMode), decode_mode(3));
+        assert_eq!(Err(ErrorKind::InvalidMode), decode_parameter(301, 1));
+    }
+
+    #[test]
+    fn run_program_reports_invalid_opcode() {
+        let error = run_program_error("1,0,0,0,42".to_string());
+        assert_eq!(ErrorKind::InvalidOpcode, error.kind);
+        assert_eq!(4, error.pc);
+        assert_eq!(42, error.instruction);
+        assert_eq!(None, error.parameter);
+    }
+
+    #[test]
+    fn run_program_reports_invalid_mode() {
+        let error = run_program_error("3001,0,0,0,99".to_string());
+        assert_eq!(ErrorKind::InvalidMode, error.kind);
+        assert_eq!(0, error.pc);
+        assert_eq!(3001, error.instruction);
+        assert_eq!(Some(2), error.parameter);
+        assert_eq!(Some(3), error.mode);
+    }
+
+    #[test]
+    fn run_program_reports_write_to_immediate() {
+        let error = run_program_error("11101,1,1,0,99".to_string());
+        assert_eq!(ErrorKind::WriteToImmediate, error.kind);
+        assert_eq!(Some(3), error.parameter);
+        assert_eq!(Some(1), error.mode);
+    }
+
+    #[test]
+    fn run_program_reports_negative_address() {
+        let error = run_program_error("109,1,204,-2,99".to_string());
+        assert_eq!(ErrorKind::NegativeAddress, error.kind);
+        assert_eq!(2, error.pc);
+        assert_eq!(Some(1), error.parameter);
+        assert_eq!(Some(2), error.mode);
+
+        let error = run_program_error("4,-5,99".to_string());
+        assert_eq!(ErrorKind::NegativeAddress, error.kind);
+    }
+
+    #[test]
+    fn run_with_uses_sources_and_sinks() {
+        // in -> [9]; out [9]; jmp 0
+        let mut emulator = prepare_emulator(
+            "3,9,4,9,1105,1,0,99,0,0".to_string(),
+            "1".to_string(),
+            false,
+        );
+        let mut values = vec![2, 3].into_iter();
+        let mut outputs = vec![];
+        let signal = emulator.run_with(&mut crate::io::from_fn(|| values.next()), &mut outputs);
+        assert_eq!(RunSignal::NoInput, signal.unwrap());
+        assert_eq!(vec![1, 2, 3], outputs);
+
+        let mut emulator = prepare_emulator("104,1,104,2,99".to_string(), "".to_string(), false);
+        let mut sum = 0;
+        let signal = emulator.run_with(
+            &mut crate::io::from_iter(vec![]),
+            &mut crate::io::to_fn(|value| sum += value),
+        );
+        assert_eq!(RunSignal::Halt, signal.unwrap());
+        assert_eq!(3, sum);
+    }
+
+    #[test]
+    fn budgets_can_be_used_up_and_topped_up() {
+        // add #1, #1 -> [7]; jt #1, #0
+        let mut emulator =
+            prepare_emulator("1101,1,1,7,1105,1,0,0".to_string(), "".to_string(), false);
+        emulator.set_instruction_budget(Some(5));
+        assert_eq!(RunSignal::BudgetExhausted, emulator.run_program().unwrap());
+        assert_eq!(RunSignal::BudgetExhausted, emulator.run_program().unwrap());
+        assert_eq!((4, 5), (emulator.pc(), emulator.instructions_executed()));
+        emulator.set_instruction_budget(Some(1));
+        assert_eq!(RunSignal::BudgetExhausted, emulator.run_program().unwrap());
+        assert_eq!((0, Some(0)), (emulator.pc(), emulator.instruction_budget()));
+
+        // waiting for input doesn't use any of the budget
+        let mut emulator = prepare_emulator("3,0,99".to_string(), "".to_string(), false);
+        emulator.set_instruction_budget(Some(1));
+        assert_eq!(RunSignal::NoInput, emulator.run_program().unwrap());
+        assert_eq!(Some(1), emulator.instruction_budget());
+    }
+
+    #[test]
+    fn infinite_loops_are_detected() {
+        let mut emulator =
+            prepare_emulator("1101,1,1,7,1105,1,0,0".to_string(), "".to_string(), false);
+        emulator.set_loop_detection(true);
+        let error = emulator.run_program().unwrap_err();
+        assert_eq!(ErrorKind::InfiniteLoop, error.kind);
+        assert_eq!(Some(0..=4), error.loop_pcs);
+        assert_eq!(
+            "InfiniteLoop at pc 0 (instruction 1101), looping over pcs 0..=4",
+            error.to_string()
+        );
+
+        // counting down changes memory every time round, so it isn't a loop until it hits 0
+        let program = assemble_to_string(
+            "
+                start:  add [n], #-1 -> [n]
+                        jt [n], #start
+                stuck:  jmp #stuck
+                n:      db 3
+            ",
+        )
+        .unwrap();
+        let mut emulator = prepare_emulator(program, "".to_string(), false);
+        emulator.set_loop_detection(true);
+        let error = emulator.run_program().unwrap_err();
+        assert_eq!((7, Some(7..=7)), (error.pc, error.loop_pcs));
+        assert_eq!(0, emulator.peek(10).unwrap());
+
+        // nor is going round the same loop reading input
+        let mut emulator =
+            prepare_emulator("3,5,1105,1,0,0".to_string(), "1,1,1".to_string(), false);
+        emulator.set_loop_detection(true);
+        assert_eq!(RunSignal::NoInput, emulator.run_program().unwrap());
+    }
+
+    #[test]
+    fn run_program_reports_out_of_bounds() {
+        let mut emulator = prepare_emulator("4,1000000,99".to_string(), "".to_string(), false);
+        emulator.set_memory_limit(Some(1000));
+        let error = emulator.run_program().unwrap_err();
+        assert_eq!(ErrorKind::OutOfBounds, error.kind);
+        assert_eq!(Some(1), error.parameter);
+
+        // writes past the limit fail just the same
+        let mut emulator = prepare_emulator("1101,1,1,5,99".to_string(), "".to_string(), false);
+        emulator.set_memory_limit(Some(4));
+        assert_eq!(Some(3), emulator.run_program().unwrap_err().parameter);
+    }
+
+    #[test]
+    fn memory_grows_on_demand() {
+        // out [1000000]; in -> [2^40]; out [2^40]; hlt
+        let mut emulator = prepare_emulator(
+            "4,1000000,3,1099511627776,4,1099511627776,99".to_string(),
+            "42".to_string(),
+            false,
+        );
+        assert_eq!(RunSignal::Output(0), emulator.run_program().unwrap());
+        assert_eq!(RunSignal::Output(42), emulator.run_program().unwrap());
+        assert_eq!(RunSignal::Halt, emulator.run_program().unwrap());
+        assert_eq!((1 << 40) + 1, emulator.program.high_water_mark());
+        assert_eq!(2, emulator.program.allocated_pages());
+    }
+
+    #[test]
+    fn failed_input_is_not_consumed() {
+        let mut emulator = prepare_emulator("103,0,99".to_string(), "7".to_string(), false);
+        assert!(emulator.run_program().is_err());
+        assert_eq!(vec![7], emulator.inputs);
+    }
+
+    fn run_words<W: Word>(program_spec: &str) -> Result<Vec<W>, IntcodeError> {
+        let program = crate::word::parse_words(program_spec).unwrap();
+        let mut emulator: Emulator<W> = Emulator::new(program, vec![], false);
+        let mut outputs = vec![];
+        emulator.run_with(&mut crate::io::from_iter(vec![]), &mut outputs)?;
+        Ok(outputs)
+    }
+
+    #[test]
+    fn runs_with_any_word_size() {
+        assert_eq!(Ok(vec![2]), run_words::<i32>("1101,1,1,7,4,7,99,0"));
+        let overflow = "1102,34915192,34915192,7,4,7,99,0";
+        assert_eq!(Ok(vec![1219070632396864]), run_words::<i64>(overflow));
+        assert_eq!(Ok(vec![1219070632396864]), run_words::<i128>(overflow));
+
+        // squaring that again needs more than 64 bits
+        let wider = "1102,34915192,34915192,13,4,13,2,13,13,13,4,13,99,0";
+        let expected = "1486133206772489918753597034496";
+        assert_eq!(
+            Ok(expected.parse::<i128>().unwrap()),
+            run_words::<i128>(wider).map(|outputs| outputs[1])
+        );
+        assert_eq!(
+            Ok(expected.to_string()),
+            run_words::<BigWord>(wider).map(|outputs| outputs[1].to_string())
+        );
+    }
+
+    #[test]
+    fn overflow_is_reported_wrapped_or_saturated() {
+        // mul #34915192, #34915192 -> [7]; out [7]; hlt
+        let program: Vec<i32> = vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0];
+        let mut emulator = Emulator::new(program.clone(), vec![], false);
+        assert_eq!(Arithmetic::Checked, emulator.arithmetic());
+        let error = emulator.run_program().unwrap_err();
+        assert_eq!((ErrorKind::Overflow, 0), (error.kind, error.pc));
+        assert_eq!(
+            "Overflow at pc 0 (instruction 1102), operands 34915192, 34915192",
+            error.to_string()
+        );
+
+        let mut emulator =
+            Emulator::new(program.clone(), vec![], false).with_arithmetic(Arithmetic::Wrapping);
+        assert_eq!(
+            RunSignal::Output(34915192i32.wrapping_mul(34915192)),
+            emulator.run_program().unwrap()
+        );
+        let mut emulator = Emulator::new(program, vec![], false);
+        emulator.set_arithmetic(Arithmetic::Saturating);
+        assert_eq!(RunSignal::Output(i32::MAX), emulator.run_program().unwrap());
+
+        // add #-2^63, #-1 -> [7]
+        let error = run_program_error("1101,-9223372036854775808,-1,7,99".to_string());
+        assert_eq!(ErrorKind::Overflow, error.kind);
+        assert_eq!(["-9223372036854775808", "-1"], *error.operands);
+    }
+
+    #[test]
+    fn words_too_wide_to_decode_are_rejected() {
+        let error = run_words::<i128>("4,100000000000000000000,99").unwrap_err();
+        assert_eq!(
+            (ErrorKind::OutOfBounds, Some(1)),
+            (error.kind, error.parameter)
+        );
+        let error = run_words::<BigWord>("204,-100000000000000000000,99").unwrap_err();
+        assert_eq!(ErrorKind::NegativeAddress, error.kind);
+        let error = run_words::<BigWord>("100000000000000000001,99").unwrap_err();
+        assert_eq!(
+            (ErrorKind::InvalidOpcode, i64::MAX),
+            (error.kind, error.instruction)
+        );
+    }
+
+    fn strict_error(program_spec: &str) -> IntcodeError {
+        let mut emulator = prepare_emulator(program_spec.to_string(), "".to_string(), false);
+        emulator.set_strict(true);
+        emulator.run_program().unwrap_err()
+    }
+
+    #[test]
+    fn strict_mode_rejects_stray_modes() {
+        // fine unless we're being strict
+        assert_eq!(Ok(vec![]), run_words::<i64>("10099"));
+        let error = strict_error("10099");
+        assert_eq!(
+            (ErrorKind::StrayMode, Some(3), Some(1)),
+            (error.kind, error.parameter, error.mode)
+        );
+        assert_eq!(
+            "StrayMode at pc 0 (instruction 10099), parameter 3, mode 1, \
+             HLT takes 0 parameters, but has a mode for parameter 3",
+            error.to_string()
+        );
+        let error = strict_error("1101,2,3,5,20104,0,99");
+        assert_eq!(
+            (ErrorKind::StrayMode, 4, Some(3), Some(2)),
+            (error.kind, error.pc, error.parameter, error.mode)
+        );
+    }
+
+    #[test]
+    fn strict_mode_rejects_immediate_writes_before_reading() {
+        // without strict mode the relative read fails first
+        let error = run_program_error("11201,-5,1,0,99".to_string());
+        assert_eq!(ErrorKind::NegativeAddress, error.kind);
+        let error = strict_error("11201,-5,1,0,99");
+        assert_eq!(
+            (ErrorKind::WriteToImmediate, Some(3)),
+            (error.kind, error.parameter)
+        );
+        assert_eq!(
+            Some("ADD writes to parameter 3, which can't be immediate"),
+            error.message.as_deref()
+        );
+    }
+
+    #[test]
+    fn strict_mode_rejects_negative_jump_targets() {
+        // the jump isn't taken, but where it would have gone is still nonsense
+        assert_eq!(Ok(vec![]), run_words::<i64>("1106,1,-1,99"));
+        let error = strict_error("1106,1,-1,99");
+        assert_eq!(
+            (ErrorKind::NegativeAddress, Some(2)),
+            (error.kind, error.parameter)
+        );
+        assert_eq!(Some("jump target -1 is negative"), error.message.as_deref());
+        assert_eq!(ErrorKind::NegativeAddress, strict_error("1105,1,-3").kind);
+    }
+
+    #[test]
+    fn strict_mode_rejects_reads_outside_the_image() {
+        assert_eq!(Ok(vec![0]), run_words::<i64>("4,5,99"));
+        let error = strict_error("4,5,99");
+        assert_eq!(
+            (ErrorKind::OutsideImage, Some(1)),
+            (error.kind, error.parameter)
+        );
+        assert_eq!(
+            Some("address 5 is outside the 3 word program image"),
+            error.message.as_deref()
+        );
+        // running off the end is reading outside it too
+        let error = strict_error("1105,1,3");
+        assert_eq!(
+            (ErrorKind::OutsideImage, 3, None),
+            (error.kind, error.pc, error.parameter)
+        );
+
+        // what the program wrote past the end still isn't part of the image once it's restored
+        let mut emulator = prepare_emulator("1101,1,1,9,4,9,99".to_string(), "".to_string(), false);
+        emulator.set_instruction_budget(Some(1));
+        emulator.run_program().unwrap();
+        let mut buffer = vec![];
+        crate::savefile::write_snapshot(&mut buffer, &emulator.snapshot()).unwrap();
+        let snapshot = crate::savefile::read_snapshot(&mut buffer.as_slice()).unwrap();
+        let mut restored = Emulator::from_snapshot(&snapshot, false);
+        restored.set_strict(true);
+        assert_eq!(7, restored.image());
+        let error = restored.run_program().unwrap_err();
+        assert_eq!((ErrorKind::OutsideImage, 4), (error.kind, error.pc));
+
+        // writing past the end is fine, only reading there isn't
+        let mut emulator = prepare_emulator("1101,1,1,9,99".to_string(), "".to_string(), false);
+        emulator.set_strict(true);
+        assert_eq!(RunSignal::Halt, emulator.run_program().unwrap());
+        assert!(emulator.is_strict());
+    }
+
+    #[test]
+    fn debugging_leaves_tracing_to_the_caller() {
+        let emulator = prepare_emulator("99".to_string(), "".to_string(), true);
+        assert!(emulator.tracer().is_none());
+        let emulator = Emulator::from_snapshot(&emulator.snapshot(), true);
+        assert!(emulator.tracer().is_none());
+    }
+
+    fn run_program_error(program_spec: String) -> IntcodeError {
+        let mut emulator = prepare_emulator(program_spec, "".to_string(), false);
+        loop {
+            match emulator.run_program() {
+                Ok(RunSignal::Halt) => panic!("program halted without an error"),
+                Ok(_) => continue,
+                Err(error) => return error,
+            }
+        }
     }
 }
@@ -0,0 +1,97 @@
+use crate::memory::Memory;
+use crate::word::Word;
+use std::collections::hash_map::Entry;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+// forget everything past this many states, a loop shorter than it is still caught next time round
+const MAX_STATES: usize = 100_000;
+
+// pc, relative base and the touched words
+type State<W> = (usize, usize, Vec<(usize, W)>);
+
+/*
+    Spots a program that's stuck in a loop it can never leave.
+    Between inputs and outputs a program is deterministic, so if it's ever back at
+    the same pc and relative base with the same memory, it'll go round forever.
+    Only the addresses written since the last reset can differ, so those are all a
+    state needs, and states are only recorded after a jump backwards since every
+    loop has to take one.
+*/
+#[derive(Debug, Clone)]
+pub struct LoopDetector<W = i64> {
+    touched: BTreeSet<usize>,
+    // and how many instructions had been executed when each was first seen
+    seen: HashMap<State<W>, u64>,
+}
+
+impl<W> Default for LoopDetector<W> {
+    fn default() -> LoopDetector<W> {
+        LoopDetector {
+            touched: BTreeSet::new(),
+            seen: HashMap::new(),
+        }
+    }
+}
+
+impl<W: Word> LoopDetector<W> {
+    // the program has done something that could change where it goes, like reading input
+    pub fn reset(&mut self) {
+        self.touched.clear();
+        self.seen.clear();
+    }
+
+    pub fn touch(&mut self, address: usize) {
+        self.touched.insert(address);
+    }
+
+    // if the program has been in exactly this state before, how many instructions in that was
+    pub fn visit(
+        &mut self,
+        pc: usize,
+        relative_base: usize,
+        memory: &Memory<W>,
+        instructions: u64,
+    ) -> Option<u64> {
+        let words = self
+            .touched
+            .iter()
+            .map(|address| (*address, memory[*address].clone()))
+            .collect();
+        if self.seen.len() >= MAX_STATES {
+            self.seen.clear();
+        }
+        match self.seen.entry((pc, relative_base, words)) {
+            Entry::Occupied(first) => Some(*first.get()),
+            Entry::Vacant(state) => {
+                state.insert(instructions);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::loops::*;
+
+    #[test]
+    fn recognises_repeated_states() {
+        let mut memory = Memory::from(vec![1, 2, 3]);
+        let mut detector: LoopDetector = LoopDetector::default();
+        assert_eq!(None, detector.visit(0, 0, &memory, 1));
+        assert_eq!(Some(1), detector.visit(0, 0, &memory, 2));
+        assert_eq!(None, detector.visit(0, 1, &memory, 3));
+
+        detector.touch(2);
+        memory[2] = 4;
+        assert_eq!(None, detector.visit(0, 0, &memory, 4));
+        memory[2] = 5;
+        assert_eq!(None, detector.visit(0, 0, &memory, 5));
+        memory[2] = 4;
+        assert_eq!(Some(4), detector.visit(0, 0, &memory, 6));
+
+        detector.reset();
+        assert_eq!(None, detector.visit(0, 0, &memory, 7));
+    }
+}
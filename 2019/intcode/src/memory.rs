@@ -0,0 +1,271 @@
+use crate::word::Word;
+use std::collections::BTreeMap;
+use std::ops::Index;
+use std::ops::IndexMut;
+use std::sync::Arc;
+
+// words per page, cloning a Memory only copies a pointer per page
+const PAGE_SIZE: usize = 1024;
+// pages below this are kept in a plain vector, anything further out goes in a map
+const DENSE_PAGES: usize = 4096;
+
+type Page<W> = Arc<Vec<W>>;
+
+/*
+    Emulator memory, split into copy-on-write pages.
+    Clones share every page until one side writes to it, so forking an emulator
+    costs a handful of pointer copies rather than a copy of the whole memory.
+
+    Memory grows on demand: a page is only allocated the first time something non-zero
+    is written to it, and everywhere else reads as 0. Low pages sit in a vector for speed,
+    so a program scribbling at address 2^40 only costs a single page in the map.
+    An optional limit makes reads and writes at or past it fail instead.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Memory<W = i64> {
+    dense: Vec<Option<Page<W>>>,
+    sparse: BTreeMap<usize, Page<W>>,
+    // one past the highest address loaded or written
+    high_water_mark: usize,
+    limit: Option<usize>,
+    // what Index hands out for addresses nothing has been written to
+    zero: W,
+}
+
+impl<W: Word> Memory<W> {
+    pub fn new() -> Memory<W> {
+        Memory::default()
+    }
+
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water_mark
+    }
+
+    pub(crate) fn raise_high_water_mark(&mut self, mark: usize) {
+        self.high_water_mark = self.high_water_mark.max(mark);
+    }
+
+    // for undoing writes, once the words above the old mark have been put back to zero
+    pub(crate) fn lower_high_water_mark(&mut self, mark: usize) {
+        self.high_water_mark = self.high_water_mark.min(mark);
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    pub fn allocated_pages(&self) -> usize {
+        self.dense.iter().flatten().count() + self.sparse.len()
+    }
+
+    fn in_limit(&self, address: usize) -> bool {
+        self.limit.is_none_or(|limit| address < limit)
+    }
+
+    fn page(&self, number: usize) -> Option<&Page<W>> {
+        match number < DENSE_PAGES {
+            true => self.dense.get(number)?.as_ref(),
+            false => self.sparse.get(&number),
+        }
+    }
+
+    fn page_mut(&mut self, number: usize) -> &mut Vec<W> {
+        let page = match number < DENSE_PAGES {
+            true => {
+                if number >= self.dense.len() {
+                    self.dense.resize(number + 1, None);
+                }
+                self.dense[number].get_or_insert_with(|| Arc::new(vec![W::default(); PAGE_SIZE]))
+            }
+            false => self
+                .sparse
+                .entry(number)
+                .or_insert_with(|| Arc::new(vec![W::default(); PAGE_SIZE])),
+        };
+        Arc::make_mut(page)
+    }
+
+    fn pages(&self) -> impl Iterator<Item = (usize, &Page<W>)> {
+        self.dense
+            .iter()
+            .enumerate()
+            .filter_map(|(number, page)| Some((number, page.as_ref()?)))
+            .chain(self.sparse.iter().map(|(number, page)| (*number, page)))
+    }
+
+    pub fn get(&self, address: usize) -> Option<W> {
+        if !self.in_limit(address) {
+            return None;
+        }
+        Some(self[address].clone())
+    }
+
+    pub fn set(&mut self, address: usize, value: W) -> bool {
+        if !self.in_limit(address) {
+            return false;
+        }
+        // no need to allocate a page just to store a zero in it
+        if value.is_zero() && self.page(address / PAGE_SIZE).is_none() {
+            self.raise_high_water_mark(address.saturating_add(1));
+        } else {
+            self[address] = value;
+        }
+        true
+    }
+
+    // the first len words, or fewer if nothing has reached that far. A single far write
+    // puts the high-water mark anywhere, so laying it all out is never safe; use chunks for
+    // everything there is
+    pub fn to_vec(&self, len: usize) -> Vec<W> {
+        (0..len.min(self.high_water_mark))
+            .map(|address| self[address].clone())
+            .collect()
+    }
+
+    // the start address and words of each allocated page, in address order
+    pub fn chunks(&self) -> impl Iterator<Item = (usize, &[W])> {
+        self.pages()
+            .map(|(number, page)| (number * PAGE_SIZE, page.as_slice()))
+    }
+
+    // how many pages are still shared with another memory, mostly to check forks stay cheap
+    pub fn shared_pages(&self, other: &Memory<W>) -> usize {
+        self.pages()
+            .filter(|(number, page)| match other.page(*number) {
+                Some(theirs) => Arc::ptr_eq(page, theirs),
+                None => false,
+            })
+            .count()
+    }
+}
+
+impl<W: Word> From<Vec<W>> for Memory<W> {
+    fn from(words: Vec<W>) -> Memory<W> {
+        let mut memory = Memory::new();
+        for (number, chunk) in words.chunks(PAGE_SIZE).enumerate() {
+            let mut page = chunk.to_vec();
+            page.resize(PAGE_SIZE, W::default());
+            *memory.page_mut(number) = page;
+        }
+        memory.high_water_mark = words.len();
+        memory
+    }
+}
+
+// only the contents count, a page of zeros is as good as no page at all
+impl<W: Word> PartialEq for Memory<W> {
+    fn eq(&self, other: &Memory<W>) -> bool {
+        let zeros = |page: &Page<W>| page.iter().all(|word| word.is_zero());
+        let same = |a: &Memory<W>, b: &Memory<W>| {
+            a.pages().all(|(number, page)| match b.page(number) {
+                Some(theirs) => Arc::ptr_eq(page, theirs) || page == theirs,
+                None => zeros(page),
+            })
+        };
+        self.high_water_mark == other.high_water_mark && same(self, other) && same(other, self)
+    }
+}
+
+impl<W: Word> Eq for Memory<W> {}
+
+impl<W: Word> Index<usize> for Memory<W> {
+    type Output = W;
+
+    fn index(&self, address: usize) -> &W {
+        match self.page(address / PAGE_SIZE) {
+            Some(page) => &page[address % PAGE_SIZE],
+            None => &self.zero,
+        }
+    }
+}
+
+impl<W: Word> IndexMut<usize> for Memory<W> {
+    fn index_mut(&mut self, address: usize) -> &mut W {
+        assert!(
+            self.in_limit(address),
+            "address {} past the memory limit",
+            address
+        );
+        self.raise_high_water_mark(address.saturating_add(1));
+        &mut self.page_mut(address / PAGE_SIZE)[address % PAGE_SIZE]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::*;
+
+    #[test]
+    fn reads_and_writes() {
+        let mut memory = Memory::from(vec![1, 2, 3]);
+        assert_eq!(3, memory.high_water_mark());
+        assert_eq!(Some(2), memory.get(1));
+        assert_eq!(Some(0), memory.get(3));
+        assert!(memory.set(2, 30));
+        memory[0] = 10;
+        assert_eq!(vec![10, 2, 30], memory.to_vec(usize::MAX));
+        assert_eq!(vec![10, 2], memory.to_vec(2));
+    }
+
+    #[test]
+    fn grows_on_demand() {
+        let mut memory = Memory::from(vec![1, 2, 3]);
+        assert_eq!(1, memory.allocated_pages());
+        assert!(memory.set(5000, 0));
+        assert_eq!(5001, memory.high_water_mark());
+        assert_eq!(1, memory.allocated_pages());
+
+        assert!(memory.set(5000, 9));
+        assert!(memory.set(1 << 40, -1));
+        assert_eq!(3, memory.allocated_pages());
+        assert_eq!(Some(9), memory.get(5000));
+        assert_eq!(-1, memory[1 << 40]);
+        assert_eq!(0, memory[(1 << 40) + PAGE_SIZE]);
+        assert_eq!((1 << 40) + 1, memory.high_water_mark());
+        assert_eq!(vec![1, 2, 3, 0], memory.to_vec(4));
+        let starts: Vec<usize> = memory.chunks().map(|(start, _)| start).collect();
+        assert_eq!(vec![0, 4 * PAGE_SIZE, 1 << 40], starts);
+    }
+
+    #[test]
+    fn enforces_a_limit() {
+        let mut memory = Memory::from(vec![1, 2, 3]);
+        memory.set_limit(Some(4));
+        assert_eq!(Some(0), memory.get(3));
+        assert_eq!(None, memory.get(4));
+        assert!(memory.set(3, 4));
+        assert!(!memory.set(4, 5));
+        assert_eq!(4, memory.high_water_mark());
+    }
+
+    #[test]
+    fn clones_copy_on_write() {
+        let original = Memory::from(vec![7; PAGE_SIZE * 4]);
+        let mut fork = original.clone();
+        assert_eq!(4, fork.shared_pages(&original));
+        assert_eq!(original, fork);
+
+        fork.set(PAGE_SIZE + 1, 8);
+        assert_eq!(3, fork.shared_pages(&original));
+        assert_eq!(Some(7), original.get(PAGE_SIZE + 1));
+        assert_eq!(Some(8), fork.get(PAGE_SIZE + 1));
+        assert_ne!(original, fork);
+
+        fork.set(PAGE_SIZE + 1, 7);
+        assert_eq!(original, fork);
+    }
+
+    #[test]
+    fn zero_pages_compare_equal_to_missing_ones() {
+        let mut memory = Memory::from(vec![1; 3]);
+        memory[PAGE_SIZE * 2] = 5;
+        memory[PAGE_SIZE * 2] = 0;
+        let mut other = Memory::from(vec![1; 3]);
+        other.set(PAGE_SIZE * 2, 0);
+        assert_eq!(memory, other);
+    }
+}
@@ -0,0 +1,346 @@
+use crate::intcode::*;
+use std::collections::VecDeque;
+use std::fmt;
+
+/*
+    A network of machines all running the same program, as in 2019 day 23.
+    Each machine's first input is its address, then it reads packets an x and a y
+    at a time, getting -1 whenever its queue is empty, and sends packets by
+    outputting a destination, x and y.
+
+    Machines take turns in address order, one packet (or -1) each per round, so
+    runs are deterministic. Packets sent to the NAT aren't delivered anywhere,
+    it just remembers the latest. Once a whole round passes with every queue empty
+    and nothing sent, the network is idle and the NAT wakes machine 0 with that packet.
+
+    A machine has a budget of instructions each turn to get round to reading input again,
+    so one that never does fails the round instead of hanging it.
+*/
+
+pub const NAT: i64 = 255;
+
+// instructions each machine can run per turn unless the budget is changed
+pub const DEFAULT_TURN_BUDGET: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Packet {
+    pub destination: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum NetworkEvent {
+    // includes packets sent to the NAT
+    Sent { from: usize, packet: Packet },
+    // the network went idle and the NAT sent this to machine 0
+    Wake(Packet),
+    // the network went idle before anything was sent to the NAT
+    Idle,
+    Halted(usize),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum NetworkError {
+    Emulator { address: usize, error: IntcodeError },
+    UnknownDestination { from: usize, packet: Packet },
+    AllHalted,
+    // used up its turn's budget without waiting for input
+    Unresponsive { address: usize, budget: u64 },
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Emulator { address, error } => {
+                write!(f, "machine {} failed: {}", address, error)
+            }
+            NetworkError::UnknownDestination { from, packet } => write!(
+                f,
+                "machine {} sent a packet to {}, which isn't on the network",
+                from, packet.destination
+            ),
+            NetworkError::AllHalted => write!(f, "every machine has halted"),
+            NetworkError::Unresponsive { address, budget } => write!(
+                f,
+                "machine {} ran {} instructions without waiting for input",
+                address, budget
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+pub struct Network {
+    machines: Vec<Emulator>,
+    queues: Vec<VecDeque<(i64, i64)>>,
+    // the start of a packet a machine is partway through sending
+    partial: Vec<Vec<i64>>,
+    nat: Option<Packet>,
+    rounds: usize,
+    budget: u64,
+}
+
+impl Network {
+    pub fn new(program: Vec<i64>, size: usize, debug: bool) -> Network {
+        Network {
+            machines: (0..size)
+                .map(|address| Emulator::new(program.clone(), vec![address as i64], debug))
+                .collect(),
+            queues: vec![VecDeque::new(); size],
+            partial: vec![vec![]; size],
+            nat: None,
+            rounds: 0,
+            budget: DEFAULT_TURN_BUDGET,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.machines.len()
+    }
+
+    // the last packet sent to the NAT
+    pub fn nat(&self) -> Option<Packet> {
+        self.nat
+    }
+
+    pub fn rounds(&self) -> usize {
+        self.rounds
+    }
+
+    pub fn machine(&self, address: usize) -> &Emulator {
+        &self.machines[address]
+    }
+
+    pub fn turn_budget(&self) -> u64 {
+        self.budget
+    }
+
+    pub fn set_turn_budget(&mut self, budget: u64) {
+        self.budget = budget;
+    }
+
+    fn deliver(&mut self, address: usize, packet: Packet) {
+        self.queues[address].push_back((packet.x, packet.y));
+    }
+
+    // a packet one of the machines sent
+    fn route(&mut self, from: usize, packet: Packet) -> Result<(), NetworkError> {
+        match packet.destination {
+            NAT => self.nat = Some(packet),
+            address if address >= 0 && (address as usize) < self.size() => {
+                self.deliver(address as usize, packet)
+            }
+            _ => return Err(NetworkError::UnknownDestination { from, packet }),
+        }
+        Ok(())
+    }
+
+    // give every machine a turn, returning what happened in order
+    pub fn round(&mut self) -> Result<Vec<NetworkEvent>, NetworkError> {
+        if self.machines.iter().all(|machine| machine.is_halted()) {
+            return Err(NetworkError::AllHalted);
+        }
+        self.rounds += 1;
+        let mut events = vec![];
+        let mut idle = true;
+        for address in 0..self.size() {
+            if self.machines[address].is_halted() {
+                continue;
+            }
+            match self.queues[address].pop_front() {
+                Some((x, y)) => {
+                    idle = false;
+                    self.machines[address].inputs.extend(&[x, y]);
+                }
+                None => self.machines[address].inputs.push(-1),
+            }
+            self.machines[address].set_instruction_budget(Some(self.budget));
+            loop {
+                let signal = self.machines[address]
+                    .run_program()
+                    .map_err(|error| NetworkError::Emulator { address, error })?;
+                match signal {
+                    RunSignal::NoInput => break,
+                    RunSignal::Halt => {
+                        events.push(NetworkEvent::Halted(address));
+                        break;
+                    }
+                    RunSignal::Output(value) => {
+                        self.partial[address].push(value);
+                        if let [destination, x, y] = self.partial[address][..] {
+                            self.partial[address].clear();
+                            let packet = Packet { destination, x, y };
+                            self.route(address, packet)?;
+                            events.push(NetworkEvent::Sent {
+                                from: address,
+                                packet,
+                            });
+                            idle = false;
+                        }
+                    }
+                    RunSignal::BudgetExhausted => {
+                        let budget = self.budget;
+                        return Err(NetworkError::Unresponsive { address, budget });
+                    }
+                    // we never set any watchpoints or breakpoints
+                    RunSignal::Watch(_) | RunSignal::Breakpoint(_) => continue,
+                }
+            }
+        }
+
+        // machines that have halted aren't waiting on anything
+        if idle && self.machines.iter().any(|machine| !machine.is_halted()) {
+            match self.nat {
+                Some(packet) => {
+                    let packet = Packet {
+                        destination: 0,
+                        ..packet
+                    };
+                    self.deliver(0, packet);
+                    events.push(NetworkEvent::Wake(packet));
+                }
+                None => events.push(NetworkEvent::Idle),
+            }
+        }
+        Ok(events)
+    }
+
+    // run rounds until something we're waiting for happens, returning it
+    pub fn run_until<F>(&mut self, mut done: F) -> Result<NetworkEvent, NetworkError>
+    where
+        F: FnMut(&NetworkEvent) -> bool,
+    {
+        loop {
+            if let Some(event) = self.round()?.into_iter().find(|event| done(event)) {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+    use crate::network::*;
+
+    // machine 0 sends (1, 0, 7), then everyone forwards what they get to the next
+    // address up with x + 1, and the last machine sends to the NAT
+    const RELAY: &str = "
+                in -> [address]
+                jt [address], #listen
+                out #1
+                out #0
+                out #7
+        listen: in -> [x]
+                eq [x], #-1 -> [t]
+                jt [t], #listen
+                in -> [y]
+                add [address], #1 -> [next]
+                eq [next], #3 -> [t]
+                jf [t], #send
+                mov #255 -> [next]
+        send:   out [next]
+                add [x], #1 -> [x]
+                out [x]
+                out [y]
+                jmp #listen
+        address: db 0
+        x:      db 0
+        y:      db 0
+        next:   db 0
+        t:      db 0
+    ";
+
+    fn packet(destination: i64, x: i64, y: i64) -> Packet {
+        Packet { destination, x, y }
+    }
+
+    #[test]
+    fn delivers_packets_and_wakes_when_idle() {
+        let mut network = Network::new(assemble(RELAY).unwrap(), 3, false);
+        assert_eq!(
+            vec![
+                NetworkEvent::Sent {
+                    from: 0,
+                    packet: packet(1, 0, 7)
+                },
+                NetworkEvent::Sent {
+                    from: 1,
+                    packet: packet(2, 1, 7)
+                },
+                NetworkEvent::Sent {
+                    from: 2,
+                    packet: packet(NAT, 2, 7)
+                },
+            ],
+            network.round().unwrap()
+        );
+        assert_eq!(Some(packet(NAT, 2, 7)), network.nat());
+        assert_eq!(
+            vec![NetworkEvent::Wake(packet(0, 2, 7))],
+            network.round().unwrap()
+        );
+
+        // how day 23 part 2 is asked: the first y the NAT sends twice in a row
+        let mut last = None;
+        let event = network
+            .run_until(|event| match event {
+                NetworkEvent::Wake(packet) => {
+                    let repeated = last == Some(packet.y);
+                    last = Some(packet.y);
+                    repeated
+                }
+                _ => false,
+            })
+            .unwrap();
+        assert_eq!(NetworkEvent::Wake(packet(0, 8, 7)), event);
+        assert_eq!(6, network.rounds());
+    }
+
+    #[test]
+    fn reports_problems() {
+        let mut network = Network::new(assemble("out #7\nout #1\nout #2\nhlt").unwrap(), 2, false);
+        assert_eq!(
+            Err(NetworkError::UnknownDestination {
+                from: 0,
+                packet: packet(7, 1, 2)
+            }),
+            network.round()
+        );
+
+        let mut network = Network::new(vec![99], 2, false);
+        assert_eq!(
+            vec![NetworkEvent::Halted(0), NetworkEvent::Halted(1)],
+            network.round().unwrap()
+        );
+        assert_eq!(Err(NetworkError::AllHalted), network.round());
+        assert_eq!(Err(NetworkError::AllHalted), network.run_until(|_| true));
+
+        let mut network = Network::new(vec![3, 0, 42], 1, false);
+        assert!(matches!(
+            network.round(),
+            Err(NetworkError::Emulator { address: 0, .. })
+        ));
+
+        // machine 1 never reads another input or sends anything
+        let spin = "
+                    in -> [address]
+                    jf [address], #done
+            spin:   jmp #spin
+            done:   in -> [address]
+                    hlt
+            address: db 0
+        ";
+        let mut network = Network::new(assemble(spin).unwrap(), 2, false);
+        network.set_turn_budget(100);
+        assert_eq!(
+            Err(NetworkError::Unresponsive {
+                address: 1,
+                budget: 100
+            }),
+            network.round()
+        );
+    }
+}
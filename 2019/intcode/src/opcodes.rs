@@ -0,0 +1,330 @@
+use crate::intcode::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+/*
+    Extra opcodes on top of the built in ones, for trying out new instructions without
+    touching the emulator. Registering one gives the emulator its metadata, which the
+    disassembler, debugger and traces use to show it, and a handler to run it.
+
+    Parameters are fetched and written just like the built in instructions', in any mode.
+    The handler gets the values of the parameters it reads, and says what to write to the
+    one it writes (if it has one), what to output and where to jump, if anywhere.
+*/
+
+// the most parameters an instruction can take, since there are only three mode digits
+pub const MAX_PARAMETERS: usize = 3;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OpcodeError {
+    // one of the built in opcodes, which can't be replaced
+    Reserved(i64),
+    // only the last two digits of an instruction are its opcode
+    OutOfRange(i64),
+    TooManyParameters(usize),
+    // the written parameter has to be one of the instruction's parameters
+    BadWrite(usize),
+}
+
+impl fmt::Display for OpcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpcodeError::Reserved(opcode) => write!(f, "opcode {} is built in", opcode),
+            OpcodeError::OutOfRange(opcode) => write!(f, "opcode {} isn't 1 to 99", opcode),
+            OpcodeError::TooManyParameters(parameters) => write!(
+                f,
+                "{} parameters is more than the {} there are modes for",
+                parameters, MAX_PARAMETERS
+            ),
+            OpcodeError::BadWrite(index) => write!(f, "there is no parameter {} to write", index),
+        }
+    }
+}
+
+impl std::error::Error for OpcodeError {}
+
+// every opcode a program can use, the built in ones and any registered on top of them
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct OpcodeTable {
+    custom: BTreeMap<i64, OpcodeInfo>,
+}
+
+impl OpcodeTable {
+    pub fn new() -> OpcodeTable {
+        OpcodeTable::default()
+    }
+
+    pub fn info(&self, opcode: i64) -> Option<OpcodeInfo> {
+        opcode_info(opcode).or_else(|| self.custom.get(&opcode).copied())
+    }
+
+    // registering an opcode again replaces it
+    pub fn register(&mut self, info: OpcodeInfo) -> Result<(), OpcodeError> {
+        if opcode_info(info.opcode).is_some() {
+            return Err(OpcodeError::Reserved(info.opcode));
+        }
+        if !(1..=99).contains(&info.opcode) {
+            return Err(OpcodeError::OutOfRange(info.opcode));
+        }
+        if info.parameters > MAX_PARAMETERS {
+            return Err(OpcodeError::TooManyParameters(info.parameters));
+        }
+        if let Some(index) = info
+            .writes
+            .filter(|index| !(1..=info.parameters).contains(index))
+        {
+            return Err(OpcodeError::BadWrite(index));
+        }
+        self.custom.insert(info.opcode, info);
+        Ok(())
+    }
+
+    pub fn custom(&self) -> impl Iterator<Item = &OpcodeInfo> {
+        self.custom.values()
+    }
+}
+
+// what a custom instruction is given, and what it does
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Operation<W = i64> {
+    pub pc: usize,
+    // the values of the parameters that are read, in order
+    pub operands: Vec<W>,
+    // written to the parameter that's written, if the opcode has one
+    pub result: Option<W>,
+    // sent out just like OUT sends it
+    pub output: Option<W>,
+    // where to go instead of the next instruction
+    pub jump: Option<usize>,
+}
+
+impl<W> Operation<W> {
+    pub fn new(pc: usize, operands: Vec<W>) -> Operation<W> {
+        Operation {
+            pc,
+            operands,
+            result: None,
+            output: None,
+            jump: None,
+        }
+    }
+}
+
+// an Err stops the emulator with a HandlerFailed error carrying the message
+pub type Handler<W> = Arc<dyn Fn(&mut Operation<W>) -> Result<(), String> + Send + Sync>;
+
+#[cfg(test)]
+mod tests {
+    use crate::disassembler::*;
+    use crate::opcodes::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    fn info(opcode: i64, parameters: usize, writes: Option<usize>) -> OpcodeInfo {
+        OpcodeInfo {
+            opcode,
+            mnemonic: "TEST",
+            parameters,
+            writes,
+        }
+    }
+
+    // mod [a], [b] -> [c]
+    fn modulo(emulator: &mut Emulator) {
+        let modulo = OpcodeInfo {
+            opcode: 10,
+            mnemonic: "MOD",
+            parameters: 3,
+            writes: Some(3),
+        };
+        let handler = |operation: &mut Operation| match operation.operands[1] {
+            0 => Err("division by zero".to_string()),
+            divisor => {
+                operation.result = Some(operation.operands[0].rem_euclid(divisor));
+                Ok(())
+            }
+        };
+        emulator.add_opcode(modulo, handler).unwrap();
+    }
+
+    #[test]
+    fn registrations_are_checked() {
+        let mut table = OpcodeTable::new();
+        assert_eq!(
+            Err(OpcodeError::Reserved(1)),
+            table.register(info(1, 0, None))
+        );
+        assert_eq!(
+            Err(OpcodeError::OutOfRange(100)),
+            table.register(info(100, 0, None))
+        );
+        assert_eq!(
+            Err(OpcodeError::TooManyParameters(4)),
+            table.register(info(10, 4, None))
+        );
+        assert_eq!(
+            Err(OpcodeError::BadWrite(2)),
+            table.register(info(10, 1, Some(2)))
+        );
+        assert_eq!(None, table.info(10));
+
+        assert_eq!(Ok(()), table.register(info(10, 2, Some(2))));
+        assert_eq!(Some(info(10, 2, Some(2))), table.info(10));
+        assert_eq!(Some("ADD"), table.info(1).map(|info| info.mnemonic));
+        assert_eq!(1, table.custom().count());
+    }
+
+    #[test]
+    fn custom_opcodes_run_and_disassemble() {
+        // mod #-7, #3 -> [7]; out [7]; hlt
+        let program = vec![1110, -7, 3, 7, 4, 7, 99, 0];
+        let mut emulator = Emulator::new(program.clone(), vec![], false);
+        modulo(&mut emulator);
+        assert_eq!(RunSignal::Output(2), emulator.run_program().unwrap());
+        assert_eq!(
+            "0000: MOD #-7, #3 -> [7]\n0004: OUT [7]\n0006: HLT\n0007: DATA 0",
+            render(&disassemble_with(&program, emulator.opcodes()))
+        );
+        // without the registration it's just data
+        assert_eq!(Op::Data(1110), disassemble(&program)[0].op);
+
+        let mut emulator = Emulator::new(vec![1110, 1, 0, 7, 99], vec![], false);
+        modulo(&mut emulator);
+        let error = emulator.run_program().unwrap_err();
+        assert_eq!(ErrorKind::HandlerFailed, error.kind);
+        assert_eq!(
+            "HandlerFailed at pc 0 (instruction 1110), division by zero",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn handlers_can_output_and_jump() {
+        // chr #72; chr #105; goto #0 ... prints "Hi" then goes back round
+        let mut emulator = Emulator::new(vec![120, 72, 120, 105, 121, 0], vec![], false);
+        let print = OpcodeInfo {
+            opcode: 20,
+            mnemonic: "CHR",
+            parameters: 1,
+            writes: None,
+        };
+        emulator
+            .add_opcode(print, |operation: &mut Operation| {
+                operation.output = Some(operation.operands[0]);
+                Ok(())
+            })
+            .unwrap();
+        let goto = OpcodeInfo {
+            opcode: 21,
+            mnemonic: "GOTO",
+            parameters: 1,
+            writes: None,
+        };
+        emulator
+            .add_opcode(goto, |operation: &mut Operation| {
+                operation.jump = Some(operation.operands[0] as usize);
+                Ok(())
+            })
+            .unwrap();
+        emulator.set_instruction_budget(Some(4));
+        emulator.set_coverage(true);
+        let mut outputs = vec![];
+        let signal = emulator.run_with(&mut crate::io::from_iter(vec![]), &mut outputs);
+        assert_eq!(RunSignal::BudgetExhausted, signal.unwrap());
+        assert_eq!(
+            "HiH",
+            outputs.iter().map(|c| *c as u8 as char).collect::<String>()
+        );
+        assert_eq!(2, emulator.pc());
+        let coverage = emulator.coverage().unwrap();
+        assert_eq!(vec![0], coverage.jump_targets(4).collect::<Vec<usize>>());
+        assert_eq!(0, coverage.jump_targets(0).count());
+
+        // registering over a built in opcode fails, and the emulator is none the worse
+        assert_eq!(
+            Err(OpcodeError::Reserved(4)),
+            emulator.add_opcode(info(4, 0, None), |_: &mut Operation| Ok(()))
+        );
+        assert_eq!(
+            Some("OUT"),
+            emulator.opcodes().info(4).map(|info| info.mnemonic)
+        );
+    }
+
+    #[test]
+    fn custom_opcodes_are_covered_and_profiled() {
+        let program = vec![1110, -7, 3, 7, 4, 7, 99, 0];
+        let mut emulator = Emulator::new(program.clone(), vec![], false);
+        modulo(&mut emulator);
+        emulator.set_coverage(true);
+        emulator.set_profiling(true);
+        while emulator.run_program().unwrap() != RunSignal::Halt {}
+
+        let coverage = emulator.coverage().unwrap();
+        assert_eq!(
+            "x.. 0000: MOD #-7, #3 -> [7]\nx.. 0004: OUT [7]\nx.. 0006: HLT\n.rw 0007: DATA 0",
+            coverage.render(&program, emulator.opcodes())
+        );
+        assert_eq!(
+            vec![("OUT", 1), ("MOD", 1), ("HLT", 1)],
+            emulator
+                .profile()
+                .unwrap()
+                .opcodes()
+                .iter()
+                .map(|c| (c.mnemonic, c.count))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn unpredictable_handlers_dont_hang_loop_detection() {
+        // nop; jt #1, #0, with a nop that fails the third time, when the loop is gone
+        // round again to find its extent
+        let mut emulator = Emulator::new(vec![20, 1105, 1, 0], vec![], false);
+        let calls = AtomicUsize::new(0);
+        emulator
+            .add_opcode(info(20, 0, None), move |_: &mut Operation| {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => Ok(()),
+                    _ => Err("third time unlucky".to_string()),
+                }
+            })
+            .unwrap();
+        emulator.set_loop_detection(true);
+        let error = emulator.run_program().unwrap_err();
+        assert_eq!(ErrorKind::HandlerFailed, error.kind);
+
+        // and a go that heads off into a different loop instead of the one that was spotted
+        let mut emulator = Emulator::new(vec![21, 1105, 1, 0, 1105, 1, 4], vec![], false);
+        let calls = AtomicUsize::new(0);
+        emulator
+            .add_opcode(info(21, 0, None), move |operation: &mut Operation| {
+                if calls.fetch_add(1, Ordering::SeqCst) >= 2 {
+                    operation.jump = Some(4);
+                }
+                Ok(())
+            })
+            .unwrap();
+        emulator.set_loop_detection(true);
+        let error = emulator.run_program().unwrap_err();
+        assert_eq!(
+            (ErrorKind::InfiniteLoop, Some(0..=4)),
+            (error.kind, error.loop_pcs)
+        );
+    }
+
+    #[test]
+    fn custom_opcodes_step_back() {
+        // add #1, #2 -> [9]; mod #7, #5 -> [9]; hlt
+        let mut emulator = Emulator::new(vec![1101, 1, 2, 9, 1110, 7, 5, 9, 99, 0], vec![], false);
+        modulo(&mut emulator);
+        emulator.set_undo_log(Some(10));
+        assert_eq!(RunSignal::Halt, emulator.run_program().unwrap());
+        assert_eq!(Some(2), emulator.peek(9));
+        emulator.step_back();
+        emulator.step_back();
+        assert_eq!((4, Some(3)), (emulator.pc(), emulator.peek(9)));
+    }
+}
@@ -0,0 +1,271 @@
+use crate::intcode::*;
+use crate::opcodes::OpcodeTable;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/*
+    Counts what a program spends its time doing, one retired instruction at a time:
+    executions per pc, per opcode and per parameter mode, plus how often each
+    backwards jump is taken, since those are the loops. Only jt and jf count as jumps,
+    so a loop a custom opcode's handler drives by setting the pc isn't reported.
+*/
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+pub struct HotLoop {
+    // the jump target and the jump itself
+    pub start: usize,
+    pub end: usize,
+    pub iterations: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct OpcodeCount {
+    pub mnemonic: &'static str,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+pub struct PcCount {
+    pub pc: usize,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+struct Report {
+    instructions: u64,
+    opcodes: Vec<OpcodeCount>,
+    modes: BTreeMap<&'static str, u64>,
+    pcs: Vec<PcCount>,
+    loops: Vec<HotLoop>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    instructions: u64,
+    pcs: HashMap<usize, u64>,
+    opcodes: [u64; 100],
+    // what each opcode counted is called, custom ones included
+    mnemonics: [&'static str; 100],
+    // position, immediate, relative
+    modes: [u64; 3],
+    // (jump target, jump) for each backwards jump taken
+    loops: HashMap<(usize, usize), u64>,
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile {
+            instructions: 0,
+            pcs: HashMap::new(),
+            opcodes: [0; 100],
+            mnemonics: [""; 100],
+            modes: [0; 3],
+            loops: HashMap::new(),
+        }
+    }
+}
+
+const MODE_NAMES: [&str; 3] = ["position", "immediate", "relative"];
+
+// biggest count first, so reports don't shuffle about between runs: pcs and loops break ties
+// by address, and opcodes are counted in order and stay that way, since the sort is stable
+fn sorted<T: Clone, K: Ord>(items: &[T], key: impl Fn(&T) -> K) -> Vec<T> {
+    let mut items = items.to_vec();
+    items.sort_by_key(key);
+    items
+}
+
+impl Profile {
+    // an instruction that ran to completion at pc, which is now next_pc
+    pub fn record(&mut self, pc: usize, instruction: i64, next_pc: usize, opcodes: &OpcodeTable) {
+        self.instructions += 1;
+        *self.pcs.entry(pc).or_insert(0) += 1;
+        let opcode = get_opcode(instruction);
+        if let Some(info) = opcodes.info(opcode) {
+            self.opcodes[opcode as usize] += 1;
+            self.mnemonics[opcode as usize] = info.mnemonic;
+            for index in 1..=info.parameters {
+                if let Ok(mode) = decode_parameter(instruction, index) {
+                    self.modes[mode as usize] += 1;
+                }
+            }
+        }
+        // only the jumps can go backwards, though halting doesn't go anywhere either.
+        // Custom opcodes can too, but there's no telling which of them are jumps
+        if (opcode == 5 || opcode == 6) && next_pc <= pc {
+            *self.loops.entry((next_pc, pc)).or_insert(0) += 1;
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn opcodes(&self) -> Vec<OpcodeCount> {
+        let counts: Vec<OpcodeCount> = (0..100)
+            .filter(|opcode| self.opcodes[*opcode] > 0)
+            .map(|opcode| OpcodeCount {
+                mnemonic: self.mnemonics[opcode],
+                count: self.opcodes[opcode],
+            })
+            .collect();
+        sorted(&counts, |c| std::cmp::Reverse(c.count))
+    }
+
+    pub fn mode_count(&self, mode: Mode) -> u64 {
+        self.modes[mode as usize]
+    }
+
+    pub fn hot_pcs(&self) -> Vec<PcCount> {
+        let counts: Vec<PcCount> = self
+            .pcs
+            .iter()
+            .map(|(pc, count)| PcCount {
+                pc: *pc,
+                count: *count,
+            })
+            .collect();
+        sorted(&counts, |c| (std::cmp::Reverse(c.count), c.pc))
+    }
+
+    pub fn hot_loops(&self) -> Vec<HotLoop> {
+        let loops: Vec<HotLoop> = self
+            .loops
+            .iter()
+            .map(|((start, end), iterations)| HotLoop {
+                start: *start,
+                end: *end,
+                iterations: *iterations,
+            })
+            .collect();
+        sorted(&loops, |l| {
+            (std::cmp::Reverse(l.iterations), l.start, l.end)
+        })
+    }
+
+    // a readable summary, listing at most top pcs and loops
+    pub fn report(&self, top: usize) -> String {
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+        let mut report = String::new();
+        writeln!(report, "instructions retired: {}", self.instructions).unwrap();
+        writeln!(report, "opcodes:").unwrap();
+        for c in self.opcodes() {
+            writeln!(
+                report,
+                "    {:<4} {:>12} {:>6.2}%",
+                c.mnemonic,
+                c.count,
+                percent(c.count)
+            )
+            .unwrap();
+        }
+        writeln!(report, "parameter modes:").unwrap();
+        for (name, count) in MODE_NAMES.iter().zip(self.modes.iter()) {
+            writeln!(report, "    {:<9} {:>12}", name, count).unwrap();
+        }
+        writeln!(report, "hot pcs:").unwrap();
+        for c in self.hot_pcs().iter().take(top) {
+            writeln!(
+                report,
+                "    {:04} {:>12} {:>6.2}%",
+                c.pc,
+                c.count,
+                percent(c.count)
+            )
+            .unwrap();
+        }
+        writeln!(report, "hot loops:").unwrap();
+        for l in self.hot_loops().iter().take(top) {
+            writeln!(
+                report,
+                "    {:04}..={:04} {:>12} iterations",
+                l.start, l.end, l.iterations
+            )
+            .unwrap();
+        }
+        report
+    }
+
+    pub fn to_json(&self) -> String {
+        let report = Report {
+            instructions: self.instructions,
+            opcodes: self.opcodes(),
+            modes: MODE_NAMES
+                .iter()
+                .copied()
+                .zip(self.modes.iter().copied())
+                .collect(),
+            pcs: self.hot_pcs(),
+            loops: self.hot_loops(),
+        };
+        serde_json::to_string_pretty(&report).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+    use crate::profiler::*;
+
+    const COUNTDOWN: &str = "
+        start:  add [n], #-1 -> [n]
+                jt [n], #start
+                out [rb+0]
+                hlt
+        n:      db 3
+    ";
+
+    fn profile() -> Profile {
+        let mut emulator = Emulator::new(assemble(COUNTDOWN).unwrap(), vec![], false);
+        emulator.set_profiling(true);
+        while emulator.run_program().unwrap() != RunSignal::Halt {}
+        emulator.profile().unwrap().clone()
+    }
+
+    #[test]
+    fn counts_instructions() {
+        let profile = profile();
+        assert_eq!(8, profile.instructions());
+        assert_eq!(
+            vec![("ADD", 3), ("JT", 3), ("OUT", 1), ("HLT", 1)],
+            profile
+                .opcodes()
+                .iter()
+                .map(|c| (c.mnemonic, c.count))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(6, profile.mode_count(Mode::Immediate));
+        assert_eq!(9, profile.mode_count(Mode::Position));
+        assert_eq!(1, profile.mode_count(Mode::Relative));
+        assert_eq!(PcCount { pc: 0, count: 3 }, profile.hot_pcs()[0]);
+        assert_eq!(
+            vec![HotLoop {
+                start: 0,
+                end: 4,
+                iterations: 2
+            }],
+            profile.hot_loops()
+        );
+    }
+
+    #[test]
+    fn reports() {
+        let profile = profile();
+        let report = profile.report(1);
+        assert!(report.contains("instructions retired: 8"), "{}", report);
+        assert!(
+            report.contains("0000..=0004            2 iterations"),
+            "{}",
+            report
+        );
+        assert!(!report.contains("0004            3"), "{}", report);
+
+        let json: serde_json::Value = serde_json::from_str(&profile.to_json()).unwrap();
+        assert_eq!(8, json["instructions"]);
+        assert_eq!("ADD", json["opcodes"][0]["mnemonic"]);
+        assert_eq!(1, json["modes"]["relative"]);
+        assert_eq!(2, json["loops"][0]["iterations"]);
+    }
+}
@@ -0,0 +1,471 @@
+use crate::intcode::*;
+use crate::word::Word;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+
+/*
+    A recording of an emulator's I/O, saved as JSON Lines, one event per line:
+
+    {"event":"input","value":5,"pc":0,"instructions":0}
+    {"event":"output","value":10,"pc":6,"instructions":2}
+    {"event":"stop","pc":10,"instructions":6,"halted":true}
+
+    Every event notes the pc of the instruction and how many instructions came before it,
+    so replaying the inputs into the same program has to reproduce the outputs exactly.
+*/
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event<W = i64> {
+    Input {
+        value: W,
+        pc: usize,
+        instructions: u64,
+    },
+    Output {
+        value: W,
+        pc: usize,
+        instructions: u64,
+    },
+    // where the recorded session finished
+    Stop {
+        pc: usize,
+        instructions: u64,
+        halted: bool,
+    },
+}
+
+impl<W> Event<W> {
+    pub fn pc(&self) -> usize {
+        match self {
+            Event::Input { pc, .. } | Event::Output { pc, .. } | Event::Stop { pc, .. } => *pc,
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        match self {
+            Event::Input { instructions, .. }
+            | Event::Output { instructions, .. }
+            | Event::Stop { instructions, .. } => *instructions,
+        }
+    }
+}
+
+impl<W: Word> fmt::Display for Event<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let at = format!(
+            "at pc {} after {} instructions",
+            self.pc(),
+            self.instructions()
+        );
+        match self {
+            Event::Input { value, .. } => write!(f, "input {} {}", value, at),
+            Event::Output { value, .. } => write!(f, "output {} {}", value, at),
+            Event::Stop { halted: true, .. } => write!(f, "halt {}", at),
+            Event::Stop { halted: false, .. } => write!(f, "stop {}", at),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Recording<W = i64> {
+    pub events: Vec<Event<W>>,
+}
+
+impl<W> Default for Recording<W> {
+    fn default() -> Recording<W> {
+        Recording { events: vec![] }
+    }
+}
+
+impl<W: Word> Recording<W> {
+    pub fn new() -> Recording<W> {
+        Recording::default()
+    }
+
+    pub fn inputs(&self) -> Vec<W> {
+        self.events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Input { value, .. } => Some(value.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn outputs(&self) -> Vec<W> {
+        self.events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Output { value, .. } => Some(value.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn stop(&self) -> Option<Event<W>> {
+        self.events
+            .last()
+            .cloned()
+            .filter(|event| matches!(event, Event::Stop { .. }))
+    }
+}
+
+// everything an emulator recorded so far, ending with where it is now
+fn stop_event<W: Word>(emulator: &Emulator<W>) -> Event<W> {
+    Event::Stop {
+        pc: emulator.pc(),
+        instructions: emulator.instructions_executed(),
+        halted: emulator.is_halted(),
+    }
+}
+
+pub fn finish_recording<W: Word>(emulator: &Emulator<W>) -> Option<Recording<W>> {
+    let mut recording = emulator.recording()?.clone();
+    recording.events.push(stop_event(emulator));
+    Some(recording)
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Divergence<W = i64> {
+    // how many events matched before this one
+    pub index: usize,
+    // None if the recording had already ended
+    pub expected: Option<Event<W>>,
+    // None if the replay stopped short
+    pub actual: Option<Event<W>>,
+    pub pc: usize,
+    pub instructions: u64,
+}
+
+impl<W: Word> fmt::Display for Divergence<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |event: &Option<Event<W>>, missing: &str| match event {
+            Some(event) => event.to_string(),
+            None => missing.to_string(),
+        };
+        write!(
+            f,
+            "diverged at pc {} after {} instructions (event {}): expected {}, got {}",
+            self.pc,
+            self.instructions,
+            self.index,
+            describe(&self.expected, "nothing more"),
+            describe(&self.actual, "nothing more")
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError<W = i64> {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    Emulator(IntcodeError),
+    Diverged(Divergence<W>),
+}
+
+impl<W: Word> fmt::Display for ReplayError<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(error) => write!(f, "{}", error),
+            ReplayError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            ReplayError::Emulator(error) => write!(f, "{}", error),
+            ReplayError::Diverged(divergence) => write!(f, "{}", divergence),
+        }
+    }
+}
+
+impl<W: Word> std::error::Error for ReplayError<W> {}
+
+impl<W> From<IntcodeError> for ReplayError<W> {
+    fn from(error: IntcodeError) -> ReplayError<W> {
+        ReplayError::Emulator(error)
+    }
+}
+
+pub fn write_recording<W: Word, O: Write>(
+    writer: &mut O,
+    recording: &Recording<W>,
+) -> io::Result<()> {
+    for event in &recording.events {
+        writeln!(writer, "{}", serde_json::to_string(event).unwrap())?;
+    }
+    Ok(())
+}
+
+pub fn read_recording<W: Word, R: BufRead>(reader: R) -> Result<Recording<W>, ReplayError<W>> {
+    let mut recording = Recording::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(ReplayError::Io)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line).map_err(|error| ReplayError::Parse {
+            line: index + 1,
+            message: error.to_string(),
+        })?;
+        recording.events.push(event);
+    }
+    Ok(recording)
+}
+
+pub fn save_recording<W: Word, P: AsRef<Path>>(
+    recording: &Recording<W>,
+    path: P,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_recording(&mut writer, recording)?;
+    writer.flush()
+}
+
+pub fn load_recording<W: Word, P: AsRef<Path>>(path: P) -> Result<Recording<W>, ReplayError<W>> {
+    let file = File::open(path).map_err(ReplayError::Io)?;
+    read_recording(io::BufReader::new(file))
+}
+
+fn check<W: Word>(
+    expected: &[Event<W>],
+    index: usize,
+    actual: &Event<W>,
+) -> Result<(), ReplayError<W>> {
+    if expected.get(index) == Some(actual) {
+        return Ok(());
+    }
+    Err(ReplayError::Diverged(Divergence {
+        index,
+        expected: expected.get(index).cloned(),
+        actual: Some(actual.clone()),
+        pc: actual.pc(),
+        instructions: actual.instructions(),
+    }))
+}
+
+// how far a replay can run past the last event of a recording that doesn't say where it stopped
+pub const REPLAY_MARGIN: u64 = 1_000_000;
+
+// run the emulator, feeding it the recorded inputs as it asks for them, and check it does
+// exactly what it did when recorded. Returns the emulator where the recording stopped
+pub fn replay<W: Word>(
+    emulator: Emulator<W>,
+    recording: &Recording<W>,
+) -> Result<Emulator<W>, ReplayError<W>> {
+    let mut emulator = emulator;
+    let expected = &recording.events;
+    let mut inputs = recording.inputs().into_iter();
+    emulator.set_recording(true);
+    // stop exactly where the recording did, rather than run on into whatever comes after.
+    // Without that to go on, allow a margin past the last event, so a replay that goes off
+    // into a loop with nothing to show for it still stops
+    let start = emulator.instructions_executed();
+    let budget = match recording.stop() {
+        Some(Event::Stop { instructions, .. }) => instructions.saturating_sub(start),
+        _ => expected
+            .last()
+            .map_or(0, |event| event.instructions().saturating_sub(start))
+            .saturating_add(REPLAY_MARGIN),
+    };
+    emulator.set_instruction_budget(Some(budget));
+    let mut checked = 0;
+    loop {
+        let signal = emulator.run_program()?;
+        let stopped = match signal {
+            RunSignal::NoInput => match inputs.next() {
+                Some(value) => {
+                    emulator.inputs.push(value);
+                    false
+                }
+                None => true,
+            },
+            RunSignal::Halt | RunSignal::BudgetExhausted => true,
+            RunSignal::Output(_) | RunSignal::Watch(_) | RunSignal::Breakpoint(_) => false,
+        };
+        // only what's happened since the last signal needs checking
+        let actual = &emulator.recording().unwrap().events;
+        for (index, event) in actual.iter().enumerate().skip(checked) {
+            check(expected, index, event)?;
+        }
+        checked = actual.len();
+        if stopped {
+            if recording.stop().is_some() {
+                check(expected, checked, &stop_event(&emulator))?;
+                checked += 1;
+            }
+            if checked < expected.len() {
+                return Err(ReplayError::Diverged(Divergence {
+                    index: checked,
+                    expected: Some(expected[checked].clone()),
+                    actual: None,
+                    pc: emulator.pc(),
+                    instructions: emulator.instructions_executed(),
+                }));
+            }
+            emulator.set_instruction_budget(None);
+            return Ok(emulator);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+    use crate::replay::*;
+    use std::collections::VecDeque;
+
+    // outputs each input doubled, until it's given a zero
+    const DOUBLER: &str = "
+        loop:   in -> [value]
+                jf [value], #done
+                mul [value], #2 -> [value]
+                out [value]
+                jt #1, #loop
+        done:   hlt
+        value:  db 0
+    ";
+
+    fn record(program: &[i64], inputs: Vec<i64>) -> Recording {
+        let mut emulator = Emulator::new(program.to_vec(), vec![], false);
+        emulator.set_recording(true);
+        let mut source = VecDeque::from(inputs);
+        emulator.run_with(&mut source, &mut vec![]).unwrap();
+        finish_recording(&emulator).unwrap()
+    }
+
+    #[test]
+    fn records_io_with_positions() {
+        let recording = record(&assemble(DOUBLER).unwrap(), vec![3, 0]);
+        assert_eq!(
+            vec![
+                Event::Input {
+                    value: 3,
+                    pc: 0,
+                    instructions: 0
+                },
+                Event::Output {
+                    value: 6,
+                    pc: 9,
+                    instructions: 3
+                },
+                Event::Input {
+                    value: 0,
+                    pc: 0,
+                    instructions: 5
+                },
+                Event::Stop {
+                    pc: 14,
+                    instructions: 8,
+                    halted: true
+                },
+            ],
+            recording.events
+        );
+        assert_eq!(vec![3, 0], recording.inputs());
+        assert_eq!(vec![6], recording.outputs());
+    }
+
+    #[test]
+    fn recordings_round_trip() {
+        let recording = record(&assemble(DOUBLER).unwrap(), vec![3, 4, 0]);
+        let mut buffer = vec![];
+        write_recording(&mut buffer, &recording).unwrap();
+        assert!(String::from_utf8_lossy(&buffer)
+            .starts_with("{\"event\":\"input\",\"value\":3,\"pc\":0,\"instructions\":0}\n"));
+        assert_eq!(recording, read_recording(buffer.as_slice()).unwrap());
+        assert!(matches!(
+            read_recording::<i64, _>("{\"event\":\"jump\"}".as_bytes()),
+            Err(ReplayError::Parse { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn replays_match() {
+        let program = assemble(DOUBLER).unwrap();
+        let recording = record(&program, vec![3, 4, 0]);
+        let replayed = replay(Emulator::new(program, vec![], false), &recording).unwrap();
+        assert!(replayed.is_halted());
+        assert_eq!(vec![6, 8], replayed.outputs);
+    }
+
+    #[test]
+    fn replays_stop_where_the_recording_did() {
+        let program = assemble(DOUBLER).unwrap();
+        // recorded while still waiting for more input
+        let recording = record(&program, vec![3]);
+        let replayed = replay(Emulator::new(program, vec![], false), &recording).unwrap();
+        assert!(!replayed.is_halted());
+        assert_eq!(0, replayed.pc());
+    }
+
+    #[test]
+    fn divergence_is_flagged() {
+        let program = assemble(DOUBLER).unwrap();
+        let recording = record(&program, vec![3, 4, 0]);
+
+        // triple instead of double
+        let mut tripler = program.clone();
+        tripler[7] = 3;
+        let error = match replay(Emulator::new(tripler, vec![], false), &recording) {
+            Err(error) => error,
+            Ok(_) => panic!("the tripler replayed cleanly"),
+        };
+        assert_eq!(
+            "diverged at pc 9 after 3 instructions (event 1): \
+             expected output 6 at pc 9 after 3 instructions, \
+             got output 9 at pc 9 after 3 instructions",
+            error.to_string()
+        );
+
+        // a program that halts straight away
+        match replay(Emulator::new(vec![99], vec![], false), &recording) {
+            Err(ReplayError::Diverged(divergence)) => {
+                assert_eq!(0, divergence.index);
+                assert_eq!(recording.events.first().cloned(), divergence.expected);
+                assert_eq!(
+                    "halt at pc 0 after 1 instructions",
+                    divergence.actual.unwrap().to_string()
+                );
+            }
+            result => panic!("unexpected {:?}", result.map(|e| e.pc())),
+        }
+
+        // or one that halts after the first output, when the recording didn't say where it stopped
+        let mut unfinished = recording.clone();
+        unfinished.events.pop();
+        let mut quitter = program.clone();
+        quitter[11] = 99;
+        match replay(Emulator::new(quitter, vec![], false), &unfinished) {
+            Err(ReplayError::Diverged(divergence)) => {
+                assert_eq!(2, divergence.index);
+                assert_eq!(None, divergence.actual);
+                assert_eq!((11, 5), (divergence.pc, divergence.instructions));
+            }
+            result => panic!("unexpected {:?}", result.map(|e| e.pc())),
+        }
+    }
+
+    #[test]
+    fn replays_that_loop_without_io_stop() {
+        let program = assemble(DOUBLER).unwrap();
+        let mut recording = record(&program, vec![3, 4, 0]);
+        recording.events.pop();
+        // goes round forever instead of outputting 8
+        let mut looper = program.clone();
+        looper[11] = 1105;
+        looper[12] = 1;
+        looper[13] = 11;
+        match replay(Emulator::new(looper, vec![], false), &recording) {
+            Err(ReplayError::Diverged(divergence)) => {
+                assert_eq!(2, divergence.index);
+                assert_eq!(None, divergence.actual);
+                assert_eq!(11, divergence.pc);
+            }
+            result => panic!("unexpected {:?}", result.map(|e| e.pc())),
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use crate::intcode::*;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+/*
+    Save file layout, version 2
+        magic       "INTC"
+        version     u16, little endian
+        everything after the version is a zigzag LEB128 varint:
+        halted      0 or 1
+        pc, relative base
+        image       how many words the program was loaded with (not in version 1)
+        memory      length, segment count, then per segment: start, word count, words
+        inputs      count, values
+        outputs     count, values
+
+    Memory is mostly zeros, so only the stretches holding something are stored.
+*/
+
+const MAGIC: &[u8; 4] = b"INTC";
+pub const VERSION: u16 = 2;
+
+// how many zeros in a row it takes to split memory into separate segments
+const SEGMENT_GAP: usize = 8;
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    NotASaveFile,
+    UnsupportedVersion(u16),
+    Corrupt(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Io(error) => write!(f, "{}", error),
+            SaveError::NotASaveFile => write!(f, "not an intcode save file"),
+            SaveError::UnsupportedVersion(version) => write!(
+                f,
+                "save file is version {}, but only versions 1 to {} can be loaded",
+                version, VERSION
+            ),
+            SaveError::Corrupt(reason) => write!(f, "save file is corrupt: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(error: io::Error) -> SaveError {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => SaveError::Corrupt("file is truncated".to_string()),
+            _ => SaveError::Io(error),
+        }
+    }
+}
+
+fn write_varint<W: Write>(writer: &mut W, value: i64) -> io::Result<()> {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<i64, SaveError> {
+    let mut zigzag: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        zigzag |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+        }
+    }
+    Err(SaveError::Corrupt("number is too long".to_string()))
+}
+
+fn read_count<R: Read>(reader: &mut R, limit: usize, what: &str) -> Result<usize, SaveError> {
+    match read_varint(reader)? {
+        count if count < 0 || count as u64 > limit as u64 => Err(SaveError::Corrupt(format!(
+            "{} {} is out of range",
+            what, count
+        ))),
+        count => Ok(count as usize),
+    }
+}
+
+fn write_values<W: Write>(writer: &mut W, values: &[i64]) -> io::Result<()> {
+    write_varint(writer, values.len() as i64)?;
+    for value in values {
+        write_varint(writer, *value)?;
+    }
+    Ok(())
+}
+
+fn read_values<R: Read>(reader: &mut R) -> Result<Vec<i64>, SaveError> {
+    let count = read_count(reader, usize::MAX, "value count")?;
+    (0..count).map(|_| read_varint(reader)).collect()
+}
+
+// the (start, end) of each stretch of memory worth saving
+fn segments(words: &[i64]) -> Vec<(usize, usize)> {
+    let mut segments: Vec<(usize, usize)> = vec![];
+    for (address, word) in words.iter().enumerate() {
+        if *word == 0 {
+            continue;
+        }
+        match segments.last_mut() {
+            Some((_, end)) if address - *end < SEGMENT_GAP => *end = address + 1,
+            _ => segments.push((address, address + 1)),
+        }
+    }
+    segments
+}
+
+pub fn write_snapshot<W: Write>(writer: &mut W, snapshot: &Snapshot) -> Result<(), SaveError> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    write_varint(writer, snapshot.is_halted as i64)?;
+    write_varint(writer, snapshot.pc as i64)?;
+    write_varint(writer, snapshot.relative_base as i64)?;
+    write_varint(writer, snapshot.image as i64)?;
+
+    // segments never span pages, so a huge sparse memory is saved without ever being laid out
+    let segments: Vec<(usize, &[i64])> = snapshot
+        .memory
+        .chunks()
+        .flat_map(|(page_start, words)| {
+            segments(words)
+                .into_iter()
+                .map(move |(start, end)| (page_start + start, &words[start..end]))
+        })
+        .collect();
+    write_varint(writer, snapshot.memory.high_water_mark() as i64)?;
+    write_varint(writer, segments.len() as i64)?;
+    for (start, words) in segments {
+        write_varint(writer, start as i64)?;
+        write_values(writer, words)?;
+    }
+
+    write_values(writer, &snapshot.inputs)?;
+    write_values(writer, &snapshot.outputs)?;
+    Ok(())
+}
+
+pub fn read_snapshot<R: Read>(reader: &mut R) -> Result<Snapshot, SaveError> {
+    let mut magic = [0; 4];
+    if reader.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        return Err(SaveError::NotASaveFile);
+    }
+    let mut version = [0; 2];
+    reader.read_exact(&mut version)?;
+    let version = u16::from_le_bytes(version);
+    if !(1..=VERSION).contains(&version) {
+        return Err(SaveError::UnsupportedVersion(version));
+    }
+
+    let is_halted = match read_varint(reader)? {
+        0 => false,
+        1 => true,
+        flag => return Err(SaveError::Corrupt(format!("halted flag is {}", flag))),
+    };
+    let pc = read_count(reader, usize::MAX, "pc")?;
+    let relative_base = read_count(reader, usize::MAX, "relative base")?;
+    let image = match version {
+        1 => None,
+        _ => Some(read_count(reader, usize::MAX, "image length")?),
+    };
+
+    let length = read_count(reader, usize::MAX, "memory length")?;
+    let mut memory = Memory::new();
+    let segments = read_count(reader, length, "segment count")?;
+    for _ in 0..segments {
+        let start = read_count(reader, length, "segment start")?;
+        let count = read_count(reader, length - start, "segment length")?;
+        for address in start..start + count {
+            memory.set(address, read_varint(reader)?);
+        }
+    }
+    memory.raise_high_water_mark(length);
+
+    let inputs = read_values(reader)?;
+    let outputs = read_values(reader)?;
+    if reader.read(&mut [0])? != 0 {
+        return Err(SaveError::Corrupt("unexpected data at the end".to_string()));
+    }
+
+    Ok(Snapshot {
+        memory,
+        pc,
+        relative_base,
+        inputs,
+        outputs,
+        is_halted,
+        // version 1 didn't keep track, so the best guess is everything
+        image: image.unwrap_or(length),
+    })
+}
+
+pub fn save<P: AsRef<Path>>(emulator: &Emulator, path: P) -> Result<(), SaveError> {
+    let mut buffer = vec![];
+    write_snapshot(&mut buffer, &emulator.snapshot())?;
+    fs::write(path, buffer)?;
+    Ok(())
+}
+
+pub fn load_snapshot<P: AsRef<Path>>(path: P) -> Result<Snapshot, SaveError> {
+    let buffer = fs::read(path)?;
+    read_snapshot(&mut buffer.as_slice())
+}
+
+pub fn load<P: AsRef<Path>>(path: P, debug: bool) -> Result<Emulator, SaveError> {
+    Ok(Emulator::from_snapshot(&load_snapshot(path)?, debug))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::savefile::*;
+
+    fn save_to_vec(emulator: &Emulator) -> Vec<u8> {
+        let mut buffer = vec![];
+        write_snapshot(&mut buffer, &emulator.snapshot()).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in &[0, 1, -1, 63, -64, 64, 300, i64::MAX, i64::MIN] {
+            let mut buffer = vec![];
+            write_varint(&mut buffer, *value).unwrap();
+            assert_eq!(*value, read_varint(&mut buffer.as_slice()).unwrap());
+        }
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        // in -> [13]; out [13]; hlt
+        let mut emulator = prepare_emulator("3,13,4,13,99".to_string(), "5,6".to_string(), false);
+        emulator.run_program().unwrap();
+        emulator.program[9000] = -12;
+        emulator.program[1 << 40] = 3;
+
+        let buffer = save_to_vec(&emulator);
+        // the gaps between the words in use don't make it into the file
+        assert!(buffer.len() < 64, "{} bytes", buffer.len());
+        let snapshot = read_snapshot(&mut buffer.as_slice()).unwrap();
+        assert_eq!(emulator.snapshot(), snapshot);
+
+        let mut loaded = Emulator::from_snapshot(&snapshot, false);
+        assert_eq!(RunSignal::Halt, loaded.run_program().unwrap());
+        assert_eq!(vec![5], loaded.outputs);
+        assert_eq!(vec![6], loaded.inputs);
+    }
+
+    #[test]
+    fn saves_and_loads_files() {
+        let path = std::env::temp_dir().join(format!("intcode-{}.sav", std::process::id()));
+        let emulator = prepare_emulator("104,7,99".to_string(), "".to_string(), false);
+        save(&emulator, &path).unwrap();
+        let mut loaded = load(&path, false).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(RunSignal::Output(7), loaded.run_program().unwrap());
+    }
+
+    #[test]
+    fn keeps_the_image_apart_from_what_was_written() {
+        // add #1, #1 -> [9]; out [9]; hlt, stopped after the add
+        let mut emulator = prepare_emulator("1101,1,1,9,4,9,99".to_string(), "".to_string(), false);
+        emulator.set_instruction_budget(Some(1));
+        emulator.run_program().unwrap();
+
+        let buffer = save_to_vec(&emulator);
+        let snapshot = read_snapshot(&mut buffer.as_slice()).unwrap();
+        assert_eq!((7, 10), (snapshot.image, snapshot.memory.high_water_mark()));
+
+        // version 1 files have no image, so all of memory counts
+        let mut old = buffer.clone();
+        old[4] = 1;
+        old.remove(9);
+        assert_eq!(10, read_snapshot(&mut old.as_slice()).unwrap().image);
+    }
+
+    #[test]
+    fn rejects_incompatible_files() {
+        let emulator = prepare_emulator("99".to_string(), "".to_string(), false);
+        let mut buffer = save_to_vec(&emulator);
+
+        let not_a_save = b"1,2,3,4,99".to_vec();
+        assert!(matches!(
+            read_snapshot(&mut not_a_save.as_slice()),
+            Err(SaveError::NotASaveFile)
+        ));
+
+        buffer[4] = 3;
+        let error = read_snapshot(&mut buffer.as_slice()).unwrap_err();
+        assert!(matches!(error, SaveError::UnsupportedVersion(3)));
+        assert_eq!(
+            "save file is version 3, but only versions 1 to 2 can be loaded",
+            error.to_string()
+        );
+        buffer[4] = 2;
+
+        let truncated = &buffer[..buffer.len() - 1];
+        assert!(matches!(
+            read_snapshot(&mut &truncated[..]),
+            Err(SaveError::Corrupt(_))
+        ));
+
+        buffer.push(0);
+        assert!(matches!(
+            read_snapshot(&mut buffer.as_slice()),
+            Err(SaveError::Corrupt(_))
+        ));
+
+        assert!(matches!(
+            load("/definitely/not/here.sav", false),
+            Err(SaveError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::intcode::*;
+use crate::word::Word;
+use std::panic;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::thread::JoinHandle;
+
+/*
+    Emulators running on their own threads, reading inputs from one channel and
+    writing outputs to another. Wire the channels between emulators however you like:
+    a thread blocks while it waits for input, and finishes once its program halts or
+    every sender feeding it has gone. Watchpoints and breakpoints are ignored, since
+    there's nobody on the thread to report them to.
+*/
+
+pub struct Finished<W: Word = i64> {
+    pub emulator: Emulator<W>,
+    // Halt, NoInput if the input channel closed before the program was done,
+    // or BudgetExhausted if the emulator was given an instruction budget
+    pub result: Result<RunSignal<W>, IntcodeError>,
+}
+
+impl<W: Word> Finished<W> {
+    pub fn memory(&self) -> &Memory<W> {
+        &self.emulator.program
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.emulator.is_halted()
+    }
+}
+
+pub struct EmulatorThread<W: Word = i64> {
+    handle: JoinHandle<Finished<W>>,
+}
+
+impl<W: Word> EmulatorThread<W> {
+    // wait for the emulator to finish and hand it back
+    pub fn join(self) -> Finished<W> {
+        match self.handle.join() {
+            Ok(finished) => finished,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+pub fn spawn_with<W: Word>(
+    mut emulator: Emulator<W>,
+    mut input: Receiver<W>,
+    mut output: Sender<W>,
+) -> EmulatorThread<W> {
+    let handle = thread::spawn(move || {
+        let result = loop {
+            match emulator.run_with(&mut input, &mut output) {
+                Ok(RunSignal::Watch(_)) | Ok(RunSignal::Breakpoint(_)) => continue,
+                result => break result,
+            }
+        };
+        Finished { emulator, result }
+    });
+    EmulatorThread { handle }
+}
+
+// an emulator on its own channels, returning where to send its inputs and read its outputs
+pub fn spawn<W: Word>(emulator: Emulator<W>) -> (Sender<W>, Receiver<W>, EmulatorThread<W>) {
+    let (input, inputs) = channel();
+    let (outputs, output) = channel();
+    (input, output, spawn_with(emulator, inputs, outputs))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::threaded::*;
+
+    // echoes its inputs until it reads a 0
+    // in -> [11]; jf [11], #10; out [11]; jmp #0; hlt; db 0
+    const ECHO: &str = "3,11,1006,11,10,4,11,1105,1,0,99,0";
+
+    fn echo() -> Emulator {
+        prepare_emulator(ECHO.to_string(), "".to_string(), false)
+    }
+
+    #[test]
+    fn runs_on_its_own_channels() {
+        let (input, output, thread) = spawn(echo());
+        input.send(3).unwrap();
+        assert_eq!(Ok(3), output.recv());
+        input.send(4).unwrap();
+        input.send(0).unwrap();
+        let finished = thread.join();
+        assert_eq!(Ok(RunSignal::Halt), finished.result);
+        assert!(finished.is_halted());
+        assert_eq!(0, finished.memory()[11]);
+        assert_eq!(vec![4], output.iter().collect::<Vec<i64>>());
+    }
+
+    #[test]
+    fn stops_when_its_input_closes() {
+        let (input, _output, thread) = spawn(echo());
+        input.send(5).unwrap();
+        drop(input);
+        let finished = thread.join();
+        assert_eq!(Ok(RunSignal::NoInput), finished.result);
+        assert!(!finished.is_halted());
+        assert_eq!(vec![5], finished.emulator.outputs);
+    }
+
+    #[test]
+    fn emulators_can_be_chained() {
+        let (input, first_inputs) = channel();
+        let (middle, second_inputs) = channel();
+        let (output, outputs) = channel();
+        let first = spawn_with(echo(), first_inputs, middle);
+        let second = spawn_with(echo(), second_inputs, output);
+        for value in &[1, 2, 3, 0] {
+            input.send(*value).unwrap();
+        }
+        // the first echo stops at the 0 without passing it on, so the second runs dry
+        assert_eq!(Ok(RunSignal::Halt), first.join().result);
+        assert_eq!(Ok(RunSignal::NoInput), second.join().result);
+        assert_eq!(vec![1, 2, 3], outputs.iter().collect::<Vec<i64>>());
+    }
+
+    #[test]
+    fn amplifiers_run_in_a_feedback_loop() {
+        // the second example from 2019 day 7 part 2
+        let program = "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,\
+                       4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
+        let phases = [9, 8, 7, 6, 5];
+        let (senders, receivers): (Vec<Sender<i64>>, Vec<Receiver<i64>>) =
+            phases.iter().map(|_| channel()).unzip();
+        for (sender, phase) in senders.iter().zip(&phases) {
+            sender.send(*phase).unwrap();
+        }
+        senders[0].send(0).unwrap();
+
+        // each amplifier feeds the next, and the last one feeds back into the first
+        let amplifiers: Vec<EmulatorThread> = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(i, inputs)| {
+                let emulator = prepare_emulator(program.to_string(), "".to_string(), false);
+                let outputs = senders[(i + 1) % phases.len()].clone();
+                spawn_with(emulator, inputs, outputs)
+            })
+            .collect();
+        let finished: Vec<Finished> = amplifiers.into_iter().map(|a| a.join()).collect();
+        assert!(finished.iter().all(|f| f.result == Ok(RunSignal::Halt)));
+        assert_eq!(Some(&139629729), finished[4].emulator.outputs.last());
+    }
+}
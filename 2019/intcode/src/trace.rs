@@ -0,0 +1,377 @@
+use crate::intcode::*;
+use crate::word::Word;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufWriter;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/*
+    A record of every instruction an emulator retires, written out as JSON Lines:
+
+    {"step":0,"pc":0,"opcode":3,"mnemonic":"IN","modes":[0],"operands":[],
+     "writes":[{"address":9,"value":5}],"input":5,"next_pc":2}
+
+    operands are the values read for each parameter that isn't written to,
+    and relative_base only appears when the instruction changed it.
+*/
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MemoryWrite<W = i64> {
+    pub address: usize,
+    pub value: W,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TraceRecord<W = i64> {
+    // how many instructions had been retired before this one
+    pub step: u64,
+    pub pc: usize,
+    pub opcode: i64,
+    pub mnemonic: String,
+    pub modes: Vec<i64>,
+    pub operands: Vec<W>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub writes: Vec<MemoryWrite<W>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative_base: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<W>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<W>,
+    pub next_pc: usize,
+}
+
+impl<W: Word> TraceRecord<W> {
+    pub fn new(step: u64, pc: usize, instruction: i64) -> TraceRecord<W> {
+        TraceRecord::with_info(step, pc, instruction, opcode_info(get_opcode(instruction)))
+    }
+
+    // for an instruction whose opcode might not be a built in one
+    pub fn with_info(
+        step: u64,
+        pc: usize,
+        instruction: i64,
+        info: Option<OpcodeInfo>,
+    ) -> TraceRecord<W> {
+        let opcode = get_opcode(instruction);
+        let (mnemonic, parameters) = match info {
+            Some(info) => (info.mnemonic, info.parameters),
+            None => ("???", 0),
+        };
+        TraceRecord {
+            step,
+            pc,
+            opcode,
+            mnemonic: mnemonic.to_string(),
+            modes: (1..=parameters)
+                .map(|i| mode_digit(instruction, i))
+                .collect(),
+            operands: vec![],
+            writes: vec![],
+            relative_base: None,
+            input: None,
+            output: None,
+            next_pc: pc,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl<W: Word> fmt::Display for TraceRecord<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} {:04}: {}", self.step, self.pc, self.mnemonic)?;
+        let operands: Vec<String> = self.operands.iter().map(|o| o.to_string()).collect();
+        if !operands.is_empty() {
+            write!(f, " {}", operands.join(", "))?;
+        }
+        for write in &self.writes {
+            write!(f, " [{}] <- {}", write.address, write.value)?;
+        }
+        if let Some(relative_base) = self.relative_base {
+            write!(f, " rb <- {}", relative_base)?;
+        }
+        if let Some(input) = &self.input {
+            write!(f, " in {}", input)?;
+        }
+        if let Some(output) = &self.output {
+            write!(f, " out {}", output)?;
+        }
+        if self.next_pc != self.pc + 1 + self.modes.len() {
+            write!(f, " -> {:04}", self.next_pc)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TraceFilter {
+    pub pcs: Option<RangeInclusive<usize>>,
+    pub opcodes: Option<Vec<i64>>,
+}
+
+impl TraceFilter {
+    pub fn matches<W>(&self, record: &TraceRecord<W>) -> bool {
+        self.pcs.as_ref().is_none_or(|pcs| pcs.contains(&record.pc))
+            && self
+                .opcodes
+                .as_ref()
+                .is_none_or(|opcodes| opcodes.contains(&record.opcode))
+    }
+
+    pub fn apply<W: Clone>(&self, records: &[TraceRecord<W>]) -> Vec<TraceRecord<W>> {
+        records
+            .iter()
+            .filter(|r| self.matches(r))
+            .cloned()
+            .collect()
+    }
+}
+
+pub trait TraceSink<W = i64>: Send {
+    fn record(&mut self, record: &TraceRecord<W>);
+}
+
+impl<W: Word> TraceSink<W> for Vec<TraceRecord<W>> {
+    fn record(&mut self, record: &TraceRecord<W>) {
+        self.push(record.clone());
+    }
+}
+
+// a record per line
+pub struct JsonLines<W> {
+    writer: W,
+    // the first write that failed, after which everything else is dropped
+    pub error: Option<io::Error>,
+}
+
+impl<W: Write> JsonLines<W> {
+    pub fn new(writer: W) -> JsonLines<W> {
+        JsonLines {
+            writer,
+            error: None,
+        }
+    }
+}
+
+impl<V: Word, W: Write + Send> TraceSink<V> for JsonLines<W> {
+    fn record(&mut self, record: &TraceRecord<V>) {
+        if self.error.is_none() {
+            if let Err(error) = writeln!(self.writer, "{}", record.to_json()) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+// cloning shares the sink, so a forked emulator keeps writing to the same trace
+#[derive(Clone)]
+pub struct Tracer<W = i64> {
+    sink: Arc<Mutex<dyn TraceSink<W>>>,
+    pub filter: TraceFilter,
+}
+
+impl<W: Word> Tracer<W> {
+    pub fn new(sink: Arc<Mutex<dyn TraceSink<W>>>) -> Tracer<W> {
+        Tracer {
+            sink,
+            filter: TraceFilter::default(),
+        }
+    }
+
+    pub fn to_file<P: AsRef<Path>>(path: P) -> io::Result<Tracer<W>> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(Tracer::new(Arc::new(Mutex::new(JsonLines::new(file)))))
+    }
+
+    pub fn with_filter(self, filter: TraceFilter) -> Tracer<W> {
+        Tracer { filter, ..self }
+    }
+
+    pub fn record(&self, record: &TraceRecord<W>) {
+        if self.filter.matches(record) {
+            self.sink.lock().unwrap().record(record);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    // 1-based, like the assembler's
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::Io(error) => write!(f, "{}", error),
+            TraceError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+pub fn read_trace<W: Word, R: BufRead>(reader: R) -> Result<Vec<TraceRecord<W>>, TraceError> {
+    let mut records = vec![];
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(TraceError::Io)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|error| TraceError::Parse {
+            line: index + 1,
+            message: error.to_string(),
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+pub fn load_trace<W: Word, P: AsRef<Path>>(path: P) -> Result<Vec<TraceRecord<W>>, TraceError> {
+    let file = File::open(path).map_err(TraceError::Io)?;
+    read_trace(io::BufReader::new(file))
+}
+
+// where two traces first differ; one of the sides is None if that trace ended first
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Divergence<W = i64> {
+    pub index: usize,
+    pub left: Option<TraceRecord<W>>,
+    pub right: Option<TraceRecord<W>>,
+}
+
+pub fn diff<W: Word>(left: &[TraceRecord<W>], right: &[TraceRecord<W>]) -> Option<Divergence<W>> {
+    let index = (0..left.len().max(right.len())).find(|i| left.get(*i) != right.get(*i))?;
+    Some(Divergence {
+        index,
+        left: left.get(index).cloned(),
+        right: right.get(index).cloned(),
+    })
+}
+
+// the records leading up to the first difference, then what each side did there
+pub fn render_diff<W: Word>(
+    left: &[TraceRecord<W>],
+    right: &[TraceRecord<W>],
+    context: usize,
+) -> String {
+    let divergence = match diff(left, right) {
+        Some(divergence) => divergence,
+        None => return format!("traces are identical ({} records)", left.len()),
+    };
+    let mut lines: Vec<String> = left[divergence.index.saturating_sub(context)..divergence.index]
+        .iter()
+        .map(|record| format!("  {}", record))
+        .collect();
+    let side = |record: &Option<TraceRecord<W>>| match record {
+        Some(record) => record.to_string(),
+        None => "(trace ended)".to_string(),
+    };
+    lines.push(format!("- {}", side(&divergence.left)));
+    lines.push(format!("+ {}", side(&divergence.right)));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trace::*;
+
+    // in -> [9]; add [9], #1 -> [9]; out [9]; arb #3; hlt
+    // where [9] is the arb operand, so the program changes how far the relative base moves
+    const PROGRAM: &str = "3,9,1001,9,1,9,4,9,109,3,99";
+
+    fn trace(input: i64, filter: TraceFilter) -> Vec<TraceRecord> {
+        let log = Arc::new(Mutex::new(vec![]));
+        let mut emulator = prepare_emulator(PROGRAM.to_string(), input.to_string(), false);
+        emulator.set_tracer(Some(Tracer::new(log.clone()).with_filter(filter)));
+        while emulator.run_program().unwrap() != RunSignal::Halt {}
+        let records = log.lock().unwrap().clone();
+        records
+    }
+
+    #[test]
+    fn records_each_instruction() {
+        let records = trace(5, TraceFilter::default());
+        assert_eq!(5, records.len());
+        assert_eq!(
+            r#"{"step":0,"pc":0,"opcode":3,"mnemonic":"IN","modes":[0],"operands":[],"writes":[{"address":9,"value":5}],"input":5,"next_pc":2}"#,
+            records[0].to_json()
+        );
+        assert_eq!("#1 0002: ADD 5, 1 [9] <- 6", records[1].to_string());
+        assert_eq!("#2 0006: OUT 6 out 6", records[2].to_string());
+        assert_eq!("#3 0008: ARB 6 rb <- 6", records[3].to_string());
+        assert_eq!("#4 0010: HLT -> 0010", records[4].to_string());
+    }
+
+    #[test]
+    fn filters_by_pc_and_opcode() {
+        let by_pc = trace(
+            5,
+            TraceFilter {
+                pcs: Some(2..=6),
+                opcodes: None,
+            },
+        );
+        assert_eq!(
+            vec![2, 6],
+            by_pc.iter().map(|r| r.pc).collect::<Vec<usize>>()
+        );
+
+        let everything = trace(5, TraceFilter::default());
+        let filter = TraceFilter {
+            pcs: None,
+            opcodes: Some(vec![3, 99]),
+        };
+        assert_eq!(
+            vec![0, 10],
+            filter
+                .apply(&everything)
+                .iter()
+                .map(|r| r.pc)
+                .collect::<Vec<usize>>()
+        );
+    }
+
+    #[test]
+    fn reads_back_and_diffs() {
+        let five = trace(5, TraceFilter::default());
+        let mut buffer = JsonLines::new(vec![]);
+        for record in &five {
+            buffer.record(record);
+        }
+        assert_eq!(five, read_trace(buffer.writer.as_slice()).unwrap());
+        assert!(matches!(
+            read_trace::<i64, _>("{}\n".as_bytes()),
+            Err(TraceError::Parse { line: 1, .. })
+        ));
+
+        assert_eq!(None, diff(&five, &five));
+        assert_eq!(
+            "traces are identical (5 records)",
+            render_diff(&five, &five, 2)
+        );
+
+        let seven = trace(7, TraceFilter::default());
+        assert_eq!(0, diff(&five, &seven).unwrap().index);
+        assert_eq!(
+            "- #0 0000: IN [9] <- 5 in 5\n+ #0 0000: IN [9] <- 7 in 7",
+            render_diff(&five, &seven, 2)
+        );
+        assert_eq!(
+            "  #3 0008: ARB 6 rb <- 6\n- #4 0010: HLT -> 0010\n+ (trace ended)",
+            render_diff(&five, &five[..4], 1)
+        );
+    }
+}
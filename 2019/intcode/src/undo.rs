@@ -0,0 +1,210 @@
+use crate::trace::MemoryWrite;
+use std::collections::VecDeque;
+
+// what it takes to put the machine back the way it was before one instruction
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UndoEntry<W = i64> {
+    pub pc: usize,
+    pub relative_base: usize,
+    pub high_water_mark: usize,
+    // the words the instruction overwrote, holding the values they had before
+    pub writes: Vec<MemoryWrite<W>>,
+    pub input: Option<W>,
+    pub output: Option<W>,
+    pub halted: bool,
+}
+
+impl<W> UndoEntry<W> {
+    pub fn new(pc: usize, relative_base: usize, high_water_mark: usize) -> UndoEntry<W> {
+        UndoEntry {
+            pc,
+            relative_base,
+            high_water_mark,
+            writes: vec![],
+            input: None,
+            output: None,
+            halted: false,
+        }
+    }
+
+    pub fn wrote(&self, address: usize) -> bool {
+        self.writes.iter().any(|write| write.address == address)
+    }
+}
+
+// the most recent instructions, oldest first, forgetting the oldest once it's full
+#[derive(Debug, Clone, Default)]
+pub struct UndoLog<W = i64> {
+    entries: VecDeque<UndoEntry<W>>,
+    capacity: usize,
+}
+
+impl<W> UndoLog<W> {
+    pub fn new(capacity: usize) -> UndoLog<W> {
+        UndoLog {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn push(&mut self, entry: UndoEntry<W>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn pop(&mut self) -> Option<UndoEntry<W>> {
+        self.entries.pop_back()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // how many instructions back the most recent entry matching the predicate is, counting from 1
+    pub fn steps_back_to<P: Fn(&UndoEntry<W>) -> bool>(&self, predicate: P) -> Option<usize> {
+        self.entries
+            .iter()
+            .rev()
+            .position(predicate)
+            .map(|position| position + 1)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &UndoEntry<W>> {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+    use crate::intcode::*;
+    use crate::undo::*;
+
+    const DOUBLER: &str = "
+        loop:   in -> [value]
+                mul [value], #2 -> [value]
+                out [value]
+                arb #1
+                jt #1, #loop
+        value:  db 0
+    ";
+
+    fn doubler(inputs: Vec<i64>) -> Emulator {
+        let mut emulator = Emulator::new(assemble(DOUBLER).unwrap(), inputs, false);
+        emulator.set_undo_log(Some(100));
+        emulator
+    }
+
+    #[test]
+    fn logs_are_bounded() {
+        let mut log: UndoLog = UndoLog::new(2);
+        for pc in 0..3 {
+            log.push(UndoEntry::new(pc, 0, 0));
+        }
+        assert_eq!(2, log.len());
+        assert_eq!(
+            vec![1, 2],
+            log.entries().map(|e| e.pc).collect::<Vec<usize>>()
+        );
+        assert_eq!(Some(2), log.steps_back_to(|e| e.pc == 1));
+        assert_eq!(None, log.steps_back_to(|e| e.pc == 0));
+        assert_eq!(Some(2), log.pop().map(|e| e.pc));
+
+        let mut off: UndoLog = UndoLog::new(0);
+        off.push(UndoEntry::new(0, 0, 0));
+        assert!(off.is_empty());
+    }
+
+    #[test]
+    fn stepping_back_undoes_everything_an_instruction_did() {
+        let mut emulator = doubler(vec![3, 4]);
+        let start = emulator.snapshot();
+        assert_eq!(RunSignal::Output(6), emulator.run_program().unwrap());
+        let after_one = emulator.snapshot();
+        assert_eq!(RunSignal::Output(8), emulator.run_program().unwrap());
+        assert_eq!(RunSignal::NoInput, emulator.run_program().unwrap());
+        assert_eq!(10, emulator.undo_depth());
+
+        for _ in 0..7 {
+            assert!(emulator.step_back().is_some());
+        }
+        assert_eq!(after_one, emulator.snapshot());
+        assert_eq!(3, emulator.instructions_executed());
+        while emulator.step_back().is_some() {}
+        assert_eq!(start, emulator.snapshot());
+        assert_eq!(0, emulator.instructions_executed());
+
+        // and going forward again does the same as before
+        assert_eq!(RunSignal::Output(6), emulator.run_program().unwrap());
+        assert_eq!(after_one, emulator.snapshot());
+    }
+
+    #[test]
+    fn runs_back_to_writes_and_inputs() {
+        let mut emulator = doubler(vec![3, 4]);
+        emulator.set_recording(true);
+        emulator.run_program().unwrap();
+        emulator.run_program().unwrap();
+        emulator.run_program().unwrap();
+
+        // back to the mul, the last instruction to write [value]
+        assert_eq!(Some(4), emulator.back_to_write(13));
+        assert_eq!(2, emulator.pc());
+        assert_eq!(Some(4), emulator.peek(13));
+
+        assert_eq!(Some(1), emulator.back_to_input());
+        assert_eq!(0, emulator.pc());
+        assert_eq!(vec![4], emulator.inputs);
+        assert_eq!(vec![6], emulator.outputs);
+        // the recording forgets the output and input that were stepped back over
+        assert_eq!(2, emulator.recording().unwrap().events.len());
+
+        // not within the history, so nothing moves
+        assert_eq!(None, emulator.back_to_write(100));
+        assert_eq!(0, emulator.pc());
+    }
+
+    #[test]
+    fn halting_and_memory_growth_are_undone() {
+        // add #1, #2 -> [5000]; hlt
+        let mut emulator = Emulator::new(vec![1101, 1, 2, 5000, 99], vec![], false);
+        emulator.set_undo_log(Some(10));
+        let start = emulator.snapshot();
+        assert_eq!(RunSignal::Halt, emulator.run_program().unwrap());
+        assert!(emulator.step_back().unwrap().halted);
+        assert!(!emulator.is_halted());
+        emulator.step_back();
+        assert_eq!(start, emulator.snapshot());
+        assert_eq!(5, emulator.program.high_water_mark());
+    }
+
+    #[test]
+    fn history_is_forgotten_when_state_is_replaced() {
+        let mut emulator = doubler(vec![3]);
+        emulator.run_program().unwrap();
+        emulator.poke(13, 9);
+        assert_eq!(0, emulator.undo_depth());
+        assert_eq!(None, emulator.step_back());
+
+        emulator.set_undo_log(None);
+        emulator.run_program().unwrap();
+        assert_eq!(0, emulator.undo_depth());
+    }
+}
@@ -0,0 +1,286 @@
+use num_bigint::BigInt;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+/*
+    The number type an emulator's memory and I/O are made of. Intcode programs are
+    mostly written with 64 bits in mind, which is the default everywhere, but i32 is
+    enough for the early puzzles, and i128 or BigWord give room for programs that
+    would overflow.
+
+    Instructions, addresses and the relative base always fit in an i64, so anything
+    that has to be decoded goes through to_i64, and a word too wide for that is
+    treated as an invalid opcode or an address out of bounds.
+*/
+
+// what add and mul do when the result doesn't fit in a word
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Arithmetic {
+    // stop with an Overflow error
+    #[default]
+    Checked,
+    // wrap around, two's complement style
+    Wrapping,
+    // clamp to the largest or smallest word
+    Saturating,
+}
+
+pub trait Word:
+    Clone
+    + fmt::Debug
+    + fmt::Display
+    + FromStr
+    + Eq
+    + Ord
+    + Hash
+    + Default
+    + Send
+    + Sync
+    + Serialize
+    + DeserializeOwned
+    + 'static
+{
+    // None if the value is too wide for the word
+    fn from_i64(value: i64) -> Option<Self>;
+
+    // 1 or 0, which every word can hold
+    fn from_bool(value: bool) -> Self;
+
+    // None if the value is too wide for an i64
+    fn to_i64(&self) -> Option<i64>;
+
+    // None if the result overflowed and the arithmetic is checked
+    fn add(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self>;
+
+    fn mul(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self>;
+
+    fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn is_negative(&self) -> bool {
+        *self < Self::default()
+    }
+
+    // the nearest i64, for reporting a word that might not fit
+    fn saturating_i64(&self) -> i64 {
+        match self.to_i64() {
+            Some(value) => value,
+            None if self.is_negative() => i64::MIN,
+            None => i64::MAX,
+        }
+    }
+}
+
+macro_rules! primitive_word {
+    ($type:ty) => {
+        impl Word for $type {
+            fn from_i64(value: i64) -> Option<$type> {
+                <$type>::try_from(value).ok()
+            }
+
+            fn from_bool(value: bool) -> $type {
+                value as $type
+            }
+
+            fn to_i64(&self) -> Option<i64> {
+                i64::try_from(*self).ok()
+            }
+
+            fn add(&self, other: &$type, arithmetic: Arithmetic) -> Option<$type> {
+                match arithmetic {
+                    Arithmetic::Checked => self.checked_add(*other),
+                    Arithmetic::Wrapping => Some(self.wrapping_add(*other)),
+                    Arithmetic::Saturating => Some(self.saturating_add(*other)),
+                }
+            }
+
+            fn mul(&self, other: &$type, arithmetic: Arithmetic) -> Option<$type> {
+                match arithmetic {
+                    Arithmetic::Checked => self.checked_mul(*other),
+                    Arithmetic::Wrapping => Some(self.wrapping_mul(*other)),
+                    Arithmetic::Saturating => Some(self.saturating_mul(*other)),
+                }
+            }
+        }
+    };
+}
+
+primitive_word!(i32);
+primitive_word!(i64);
+primitive_word!(i128);
+
+// arbitrary precision, for programs whose numbers would overflow anything fixed.
+// It never overflows, so every arithmetic policy gives the same answer
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BigWord(pub BigInt);
+
+impl Word for BigWord {
+    fn from_i64(value: i64) -> Option<BigWord> {
+        Some(BigWord(BigInt::from(value)))
+    }
+
+    fn from_bool(value: bool) -> BigWord {
+        BigWord(BigInt::from(value as i64))
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        i64::try_from(&self.0).ok()
+    }
+
+    fn add(&self, other: &BigWord, _: Arithmetic) -> Option<BigWord> {
+        Some(BigWord(&self.0 + &other.0))
+    }
+
+    fn mul(&self, other: &BigWord, _: Arithmetic) -> Option<BigWord> {
+        Some(BigWord(&self.0 * &other.0))
+    }
+}
+
+impl From<i64> for BigWord {
+    fn from(value: i64) -> BigWord {
+        BigWord(BigInt::from(value))
+    }
+}
+
+impl fmt::Display for BigWord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for BigWord {
+    type Err = num_bigint::ParseBigIntError;
+
+    fn from_str(text: &str) -> Result<BigWord, Self::Err> {
+        Ok(BigWord(text.parse()?))
+    }
+}
+
+// written as a JSON number when it fits in an i64, and as a string of digits when it doesn't
+impl Serialize for BigWord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.to_i64() {
+            Some(value) => serializer.serialize_i64(value),
+            None => serializer.collect_str(&self.0),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BigWord {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<BigWord, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Number(i64),
+            Digits(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Number(value) => Ok(BigWord::from(value)),
+            Repr::Digits(digits) => digits.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+// parse a program, or a list of inputs, in whichever word size it's going to run with
+pub fn parse_words<W: Word>(text: &str) -> Result<Vec<W>, String> {
+    text.trim()
+        .split(',')
+        .filter(|word| !word.trim().is_empty())
+        .map(|word| {
+            word.trim()
+                .parse()
+                .map_err(|_| format!("'{}' isn't a number", word.trim()))
+        })
+        .collect()
+}
+
+pub fn words_from_i64<W: Word>(values: &[i64]) -> Result<Vec<W>, String> {
+    values
+        .iter()
+        .map(|value| W::from_i64(*value).ok_or(format!("{} doesn't fit in a word", value)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::word::*;
+
+    #[test]
+    fn converts_to_and_from_i64() {
+        assert_eq!(Some(-5), i32::from_i64(-5));
+        assert_eq!(None, i32::from_i64(1 << 40));
+        assert_eq!(1, i32::from_bool(true));
+        assert_eq!(BigWord::from(0), BigWord::from_bool(false));
+        assert_eq!(Ok(vec![3, -4]), words_from_i64::<i32>(&[3, -4]));
+        assert_eq!(
+            Err("-1099511627776 doesn't fit in a word".to_string()),
+            words_from_i64::<i32>(&[3, -(1 << 40)])
+        );
+        assert_eq!(None, (i64::MAX as i128 + 1).to_i64());
+        assert_eq!(i64::MAX, (i64::MAX as i128 + 1).saturating_i64());
+        assert_eq!(i64::MIN, BigWord(BigInt::from(i128::MIN)).saturating_i64());
+        assert_eq!(Some(7), BigWord::from(7).to_i64());
+        assert!(BigWord::default().is_zero());
+        assert!(BigWord::from(-1).is_negative());
+    }
+
+    #[test]
+    fn overflow_follows_the_arithmetic() {
+        assert_eq!(None, i32::MAX.add(&1, Arithmetic::Checked));
+        assert_eq!(Some(i32::MIN), i32::MAX.add(&1, Arithmetic::Wrapping));
+        assert_eq!(Some(i32::MAX), i32::MAX.add(&1, Arithmetic::Saturating));
+        assert_eq!(Some(6), 2i64.mul(&3, Arithmetic::Checked));
+        assert_eq!(None, i64::MIN.mul(&-1, Arithmetic::Checked));
+        assert_eq!(Some(i64::MIN), i64::MIN.mul(&-1, Arithmetic::Wrapping));
+        assert_eq!(Some(i128::MIN), i128::MIN.mul(&2, Arithmetic::Saturating));
+    }
+
+    #[test]
+    fn big_words_do_not_overflow() {
+        let big = BigWord::from(i64::MAX);
+        let squared = big.mul(&big, Arithmetic::Checked).unwrap();
+        assert_eq!(
+            "85070591730234615847396907784232501249",
+            squared.to_string()
+        );
+        assert_eq!(squared, squared.to_string().parse().unwrap());
+        assert_eq!(None, squared.to_i64());
+    }
+
+    #[test]
+    fn big_words_serialize_as_numbers_when_they_can() {
+        let small = BigWord::from(-12);
+        let huge = BigWord::from(i64::MAX)
+            .add(&BigWord::from(1), Arithmetic::Wrapping)
+            .unwrap();
+        assert_eq!("-12", serde_json::to_string(&small).unwrap());
+        assert_eq!(
+            "\"9223372036854775808\"",
+            serde_json::to_string(&huge).unwrap()
+        );
+        assert_eq!(small, serde_json::from_str("-12").unwrap());
+        assert_eq!(
+            huge,
+            serde_json::from_str("\"9223372036854775808\"").unwrap()
+        );
+    }
+
+    #[test]
+    fn parses_words_of_any_width() {
+        assert_eq!(Ok(vec![1, -2, 3]), parse_words::<i32>("1, -2,3\n"));
+        assert_eq!(
+            Ok(vec![i128::MAX]),
+            parse_words::<i128>(&i128::MAX.to_string())
+        );
+        assert!(parse_words::<i32>("1,2,99999999999").is_err());
+        assert_eq!(Ok(vec![]), parse_words::<i64>(""));
+    }
+}